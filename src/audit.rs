@@ -0,0 +1,243 @@
+use crate::{
+    client::AMOUNT_TOLERANCE, AccountStatus, Amount, ClientAccount, ClientAccountInfo,
+    EngineConfig, Error, Transaction, TransactionID, TransactionType,
+};
+
+/// the balances of an account before a transaction, to check what the transaction did to them
+pub(crate) struct AccountSnapshot {
+    info: ClientAccountInfo,
+    // the transaction disputes a withdrawal that is held as credit
+    holds_credit: bool,
+    // what is left of the transaction to dispute, and what its open dispute holds
+    // the money a dispute, resolve or chargeback should move is worked out from them
+    outstanding: Option<Amount>,
+    disputed: Option<Amount>,
+}
+
+impl AccountSnapshot {
+    pub(crate) fn take(account: &ClientAccount, tx: TransactionID) -> Self {
+        Self {
+            info: account.info.clone(),
+            holds_credit: account.holds_credit(tx),
+            outstanding: account.outstanding(tx),
+            disputed: account.open_dispute_amount(tx),
+        }
+    }
+}
+
+// the rounding error of f32 grows with the amounts
+fn same_amount(a: Amount, b: Amount) -> bool {
+    (a - b).abs() <= AMOUNT_TOLERANCE.max(a.abs().max(b.abs()) * 1e-6)
+}
+
+// the money of the account, the debt taken out of the balances was lent to it
+pub(crate) fn net_funds(info: &ClientAccountInfo, config: &EngineConfig) -> Amount {
+    if config.debt.repay_first {
+        info.total - info.debt
+    } else {
+        info.total
+    }
+}
+
+// the money the transaction brings into the account(negative when it leaves)
+// it comes from the row and the dispute before it, never from the balances it is checked against
+// a dispute only moves the funds between available and held, but a withdrawal
+// held as credit gives the client the money until it is resolved
+fn expected_flow(before: &AccountSnapshot, transaction: &Transaction) -> Amount {
+    // the part of the transaction or of its dispute the row covers, signed like the transaction
+    let covered = |remaining: Option<Amount>| {
+        let remaining = remaining.unwrap_or_default();
+        transaction
+            .amount()
+            .unwrap_or(remaining.abs())
+            .min(remaining.abs())
+            .copysign(remaining)
+    };
+    match transaction.t_type() {
+        TransactionType::Deposit => transaction.amount().unwrap_or_default(),
+        TransactionType::Withdrawal => -transaction.amount().unwrap_or_default(),
+        // the withdrawn amount comes back as held funds, and leaves again on the resolve
+        TransactionType::Dispute if before.holds_credit => -covered(before.outstanding),
+        TransactionType::Resolve if before.holds_credit => covered(before.disputed),
+        // the held funds leave the system, a withdrawal held as credit stays with the client
+        TransactionType::Chargeback if !before.holds_credit => -covered(before.disputed),
+        _ => 0f32,
+    }
+}
+
+/// check the invariants of the account after `transaction` was applied(`applied`) or rejected
+/// `by_policy` tells whether the status policy applies, it doesn't for an expired dispute
+/// the transaction only reaches one account, so the money of the other accounts is left alone
+/// returns the money the transaction brought in, for the ledger of the engine
+pub(crate) fn check_account(
+    before: &AccountSnapshot,
+    account: &ClientAccount,
+    transaction: &Transaction,
+    applied: bool,
+    by_policy: bool,
+    config: &EngineConfig,
+) -> Result<Amount, Error> {
+    let violation = |reason: String| {
+        Err(Error::InvariantViolation {
+            client: account.info.client,
+            tx: transaction.id(),
+            reason,
+        })
+    };
+    let info = &account.info;
+    if !same_amount(info.total, info.available + info.held) {
+        return violation(format!(
+            "total {} isn't available {} + held {}",
+            info.total, info.available, info.held
+        ));
+    }
+    let disputed = account.disputed_funds();
+    if !same_amount(info.held, disputed) {
+        return violation(format!(
+            "held {} isn't the {} of the open disputes",
+            info.held, disputed
+        ));
+    }
+    let unchanged = [
+        (before.info.available, info.available),
+        (before.info.held, info.held),
+        (before.info.total, info.total),
+        (before.info.debt, info.debt),
+    ]
+    .iter()
+    .all(|(before, after)| same_amount(*before, *after));
+    if !applied && !unchanged {
+        return violation("the rejected transaction changed the balances".to_string());
+    }
+    if by_policy
+        && before.info.status == AccountStatus::Locked
+        && !config
+            .status_policy
+            .allows(AccountStatus::Locked, transaction.t_type())
+        && !unchanged
+    {
+        return violation(format!(
+            "the {:?} changed the balances of the locked account",
+            transaction.t_type()
+        ));
+    }
+    if !applied {
+        return Ok(0f32);
+    }
+    let flow = expected_flow(before, transaction);
+    let moved = net_funds(info, config) - net_funds(&before.info, config);
+    if !same_amount(moved, flow) {
+        return violation(format!("the funds moved by {} instead of {}", moved, flow));
+    }
+    Ok(flow)
+}
+
+/// check the money of every account against the ledger of the engine, what the deposits,
+/// withdrawals, chargebacks and held credits brought in so far
+pub(crate) fn check_ledger<'a>(
+    accounts: impl Iterator<Item = &'a ClientAccountInfo>,
+    ledger: f64,
+    config: &EngineConfig,
+) -> Result<(), Error> {
+    let (funds, scale) = accounts.fold((0f64, 0f64), |(funds, scale), info| {
+        let net = net_funds(info, config) as f64;
+        (funds + net, scale + net.abs())
+    });
+    // every account brings its own rounding error
+    let tolerance = (AMOUNT_TOLERANCE as f64).max(scale * 1e-6);
+    if (funds - ledger).abs() > tolerance {
+        return Err(Error::LedgerMismatch {
+            funds,
+            expected: ledger,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_account, AccountSnapshot};
+    use crate::{
+        ClientAccount, EngineConfig, Error, Transaction, TransactionHashmapDB, TransactionType,
+        WithdrawalDisputeMode,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn apply(account: &mut ClientAccount, transaction: &Transaction) -> Result<(), Error> {
+        let before = AccountSnapshot::take(account, transaction.id());
+        let applied = account.process_transaction(transaction).is_ok();
+        check_account(
+            &before,
+            account,
+            transaction,
+            applied,
+            true,
+            &EngineConfig::default(),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn test_invariants_hold() {
+        let config = EngineConfig {
+            withdrawal_dispute: WithdrawalDisputeMode::HeldCredit,
+            ..EngineConfig::default()
+        };
+        let mut account = ClientAccount::new_with_config(
+            1,
+            Rc::new(RefCell::new(TransactionHashmapDB::new())),
+            Rc::new(config),
+        );
+        for (t_type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(10.0)),
+            (TransactionType::Withdrawal, 2, Some(4.0)),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Dispute, 1, Some(3.0)),
+            (TransactionType::Resolve, 2, None),
+            (TransactionType::Chargeback, 1, None),
+            // refused by the locked account
+            (TransactionType::Deposit, 3, Some(1.0)),
+        ] {
+            let transaction = Transaction::new(t_type, 1, tx, amount);
+            apply(&mut account, &transaction).unwrap();
+        }
+        assert_eq!(account.info.total, 3.0);
+    }
+
+    #[test]
+    fn test_violations() {
+        let mut account = ClientAccount::new(1);
+        let deposit = Transaction::new(TransactionType::Deposit, 1, 1, Some(10.0));
+        apply(&mut account, &deposit).unwrap();
+        // the funds changed behind the back of the engine
+        account.info.held = 1.0;
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 2, Some(1.0));
+        assert!(matches!(
+            apply(&mut account, &withdrawal),
+            Err(Error::InvariantViolation {
+                client: 1,
+                tx: 2,
+                ..
+            })
+        ));
+        account.info.held = 0.0;
+        account.info.total = 9.0;
+        account.info.available = 9.0;
+        // money that came out of nowhere
+        let before = AccountSnapshot::take(&account, withdrawal.id());
+        account.process_transaction(&withdrawal).unwrap();
+        account.info.available += 5.0;
+        account.info.total += 5.0;
+        assert!(matches!(
+            check_account(
+                &before,
+                &account,
+                &withdrawal,
+                true,
+                true,
+                &EngineConfig::default()
+            ),
+            Err(Error::InvariantViolation { tx: 2, .. })
+        ));
+    }
+}
@@ -1,22 +1,71 @@
-use serde::{Serialize, Serializer};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fmt,
+    rc::Rc,
+};
+
+use tracing::{info, trace};
 
 use crate::{
-    Amount, ClientID, Error, Transaction, TransactionHashmapDB, TransactionID, TransactionType,
+    format_amount, Amount, ChargebackRecord, ClientID, DisputeBelowLimit, DisputeEvent,
+    DisputeExpiryAction, DisputeInfo, EngineConfig, Error, Timestamp, Transaction,
+    TransactionHashmapDB, TransactionID, TransactionType, WithdrawalDisputeMode, DEFAULT_PRECISION,
 };
 
-fn precision_serialize<S>(x: &Amount, s: S) -> Result<S::Ok, S::Error>
+// a rounding error of the partial amounts below this is ignored
+pub(crate) const AMOUNT_TOLERANCE: Amount = 0.00005;
+
+// the amounts of the structs serialized as they are, the reports pass their own precision
+pub(crate) fn precision_serialize<S>(x: &Amount, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    //let x_str = format!("{:.4}", x);
-    //s.serialize_str(&x_str)
-    let x = (x * 10000.0).round() / 10000.0;
-    s.serialize_f32(x)
+    s.serialize_str(&format_amount(*x, DEFAULT_PRECISION))
+}
+
+/// the status of an account, it is changed by the administrative operations
+/// and a chargeback locks the account
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    // no withdrawal, everything else goes through
+    Frozen,
+    // no transaction at all
+    Locked,
+    // closed for good with zero balance
+    Closed,
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// a change of the account status, kept in the audit trail of the account
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub client: ClientID,
+    // the transaction that changed the status
+    pub tx: TransactionID,
+    pub from: AccountStatus,
+    pub to: AccountStatus,
+    pub reason: Option<String>,
 }
 
 // the information of client account
-#[derive(Serialize, Clone)]
+// it could be read back from the csv output, e.g. as a snapshot to replay from
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ClientAccountInfo {
     pub client: ClientID,
     // the total funds that are available for trading, staking, withdrawal, etc
@@ -28,9 +77,11 @@ pub struct ClientAccountInfo {
     // the total funds that are available or held
     #[serde(serialize_with = "precision_serialize")]
     pub total: Amount,
-    // the accound is locked if a charge back occurs
-    #[serde(rename = "locked")]
-    pub is_locked: bool,
+    // what the client owes beyond the overdraft limit, see `DebtPolicy`
+    #[serde(default, serialize_with = "precision_serialize")]
+    pub debt: Amount,
+    // active, frozen or locked by the admin rows or a chargeback, or closed, see `StatusPolicy`
+    pub status: AccountStatus,
 }
 
 impl ClientAccountInfo {
@@ -40,9 +91,30 @@ impl ClientAccountInfo {
             available: 0f32,
             held: 0f32,
             total: 0f32,
-            is_locked: false,
+            debt: 0f32,
+            status: AccountStatus::Active,
         }
     }
+
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
+
+    pub fn in_debt(&self) -> bool {
+        self.debt > 0f32
+    }
+}
+
+/// a dispute that is still open, it holds all or part of the transaction
+#[derive(Clone, Debug)]
+struct OpenDispute {
+    // the held amount, negative for a withdrawal like in the database
+    amount: Amount,
+    // the timestamp of the dispute row that opened it
+    opened_at: Option<Timestamp>,
+    // why the dispute was raised, from the dispute row that opened it
+    reason_code: Option<String>,
+    note: Option<String>,
 }
 
 /// the account of client. it process all transactions belong to the account
@@ -50,9 +122,23 @@ pub struct ClientAccount {
     pub info: ClientAccountInfo,
     // a pointer to a transaction database
     transaction_db: Rc<RefCell<TransactionHashmapDB>>,
+    // the policies shared by every account of the engine
+    config: Rc<EngineConfig>,
     // the transactions that are in dispute
     // record the amount to avoid double checking
-    dispute_transactions: HashMap<TransactionID, Amount>,
+    dispute_transactions: HashMap<TransactionID, OpenDispute>,
+    // every change of the account status
+    audit_trail: Vec<AuditEntry>,
+    // every chargeback of the account, for the loss report
+    chargebacks: Vec<ChargebackRecord>,
+    // the disputes, resolves and chargebacks of each transaction
+    dispute_history: HashMap<TransactionID, Vec<DisputeEvent>>,
+    // the number of disputes of the account, and the timestamps of the recent ones
+    // to apply `dispute_limits.max_per_client`
+    dispute_count: u32,
+    recent_disputes: VecDeque<Timestamp>,
+    // how far below zero the available funds could go
+    overdraft_limit: Amount,
 }
 
 impl ClientAccount {
@@ -61,62 +147,120 @@ impl ClientAccount {
         Self {
             info,
             transaction_db: Rc::new(RefCell::new(TransactionHashmapDB::new())),
+            config: Rc::new(EngineConfig::default()),
+            overdraft_limit: 0f32,
             dispute_transactions: HashMap::new(),
+            audit_trail: Vec::new(),
+            chargebacks: Vec::new(),
+            dispute_history: HashMap::new(),
+            dispute_count: 0,
+            recent_disputes: VecDeque::new(),
         }
     }
 
     pub fn new_with_db(
         clinet_id: ClientID,
         transaction_db: Rc<RefCell<TransactionHashmapDB>>,
+    ) -> Self {
+        Self::new_with_config(clinet_id, transaction_db, Rc::new(EngineConfig::default()))
+    }
+
+    pub fn new_with_config(
+        clinet_id: ClientID,
+        transaction_db: Rc<RefCell<TransactionHashmapDB>>,
+        config: Rc<EngineConfig>,
     ) -> Self {
         let info = ClientAccountInfo::new(clinet_id);
         Self {
             info,
             transaction_db,
+            overdraft_limit: config.overdraft.limit_of(clinet_id),
+            config,
             dispute_transactions: HashMap::new(),
+            audit_trail: Vec::new(),
+            chargebacks: Vec::new(),
+            dispute_history: HashMap::new(),
+            dispute_count: 0,
+            recent_disputes: VecDeque::new(),
+        }
+    }
+
+    // why a transaction is refused by the status of the account
+    fn status_error(&self) -> Error {
+        let client = self.info.client;
+        match self.info.status {
+            AccountStatus::Frozen => Error::AccountFrozen { client },
+            AccountStatus::Closed => Error::AccountClosed { client },
+            _ => Error::AccountLocked { client },
         }
     }
 
     // validate the transaction by checking the amount is a valid(we could guarantee that when we parse the data, but it is still good to check it here)
     fn validate_transaction(&self, transaction: &Transaction) -> Result<(), Error> {
-        if self.info.is_locked {
-            return Err(Error::AccountLocked {
-                client: self.info.client,
-            });
+        // the administrative operations check the status themselves
+        let t_type = transaction.t_type();
+        if !t_type.is_admin() && !self.config.status_policy.allows(self.info.status, t_type) {
+            return Err(self.status_error());
         }
 
         if transaction.client_id() != self.info.client {
             return Err(Error::WrongAccount);
         }
         match transaction.t_type() {
-            TransactionType::Deposit | TransactionType::Withdrawal => transaction
-                .amount()
-                .ok_or(Error::InvalidTransaction {
-                    client: self.info.client,
-                    tx: transaction.id(),
-                    t_type: transaction.t_type(),
-                    amount: transaction.amount(),
-                })
-                .and_then(|amount| {
-                    if amount < 0f32 {
-                        return Err(Error::InvalidTransaction {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Overdraft => {
+                transaction
+                    .amount()
+                    .ok_or(Error::InvalidTransaction {
+                        client: self.info.client,
+                        tx: transaction.id(),
+                        t_type: transaction.t_type(),
+                        amount: transaction.amount(),
+                    })
+                    .and_then(|amount| {
+                        // an infinite or NaN limit would let every withdrawal through
+                        if amount < 0f32
+                            || (t_type == TransactionType::Overdraft && !amount.is_finite())
+                        {
+                            return Err(Error::InvalidTransaction {
+                                client: self.info.client,
+                                tx: transaction.id(),
+                                t_type: transaction.t_type(),
+                                amount: transaction.amount(),
+                            });
+                        }
+                        Ok(())
+                    })
+            }
+            // the amount is optional here, it covers part of the disputed transaction
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                match transaction.amount() {
+                    Some(amount) if amount <= 0f32 || amount.is_nan() => {
+                        Err(Error::InvalidTransaction {
                             client: self.info.client,
                             tx: transaction.id(),
                             t_type: transaction.t_type(),
                             amount: transaction.amount(),
-                        });
+                        })
                     }
-                    Ok(())
-                }),
+                    _ => Ok(()),
+                }
+            }
             _ => Ok(()),
         }
     }
 
     /// deposit some amount into the account. It is always welcome to deposit :)
+    /// the debt taken out of the balances is paid back first
     fn deposit(&mut self, transaction: &Transaction) -> Result<(), Error> {
         if let Some(amount) = transaction.amount() {
-            self.info.available += amount;
-            self.info.total += amount;
+            let repaid = if self.config.debt.repay_first {
+                amount.min(self.info.debt)
+            } else {
+                0f32
+            };
+            self.info.debt -= repaid;
+            self.info.available += amount - repaid;
+            self.info.total += amount - repaid;
             self.transaction_db
                 .borrow_mut()
                 .add_transaction(transaction);
@@ -127,8 +271,12 @@ impl ClientAccount {
     /// withdrawal money from the account
     pub fn withdrawal(&mut self, transaction: &Transaction) -> Result<(), Error> {
         if let Some(amount) = transaction.amount() {
-            // sufficient account and sufficient available
-            if self.info.total < amount || self.info.available < amount {
+            // sufficient available, and sufficient account if the policy asks for it
+            // both could go down to the overdraft limit
+            let limit = self.overdraft_limit;
+            if (self.config.withdrawal_requires_total && self.info.total + limit < amount)
+                || self.info.available + limit < amount
+            {
                 return Err(Error::NoSufficientFunds {
                     client: self.info.client,
                     available: self.info.available,
@@ -145,86 +293,587 @@ impl ClientAccount {
         Ok(())
     }
 
+    // the disputed withdrawal(recorded as negative amount) is held as credit
+    fn is_held_credit(&self, tx_amount: Amount) -> bool {
+        tx_amount < 0f32 && self.config.withdrawal_dispute == WithdrawalDisputeMode::HeldCredit
+    }
+
+    // keep the covered amount in the dispute history of the transaction
+    fn record_dispute_event(&mut self, transaction: &Transaction, amount: Amount) {
+        self.dispute_history
+            .entry(transaction.id())
+            .or_default()
+            .push(DisputeEvent {
+                t_type: transaction.t_type(),
+                amount: amount.abs(),
+                timestamp: transaction.timestamp(),
+            });
+        if transaction.t_type() == TransactionType::Dispute {
+            self.dispute_count += 1;
+            if let Some(timestamp) = transaction.timestamp() {
+                self.recent_disputes.push_back(timestamp);
+            }
+        }
+    }
+
+    // check the dispute against `dispute_limits`
+    // every dispute row counts, so the partial disputes of a transaction count one by one
+    fn check_dispute_limits(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        let limits = self.config.dispute_limits;
+        if let Some(limit) = limits.max_per_transaction {
+            let disputed = self
+                .dispute_history(transaction.id())
+                .iter()
+                .filter(|event| event.t_type == TransactionType::Dispute)
+                .count();
+            if disputed >= limit as usize {
+                return Err(Error::TransactionDisputeLimit {
+                    client: self.info.client,
+                    tx: transaction.id(),
+                    limit,
+                });
+            }
+        }
+        if let Some(limit) = limits.max_per_client {
+            let disputed = match (limits.period, transaction.timestamp()) {
+                (Some(period), Some(now)) => {
+                    // the disputes as old as the period don't count anymore
+                    // they could come out of order, so every one of them is looked at
+                    self.recent_disputes
+                        .retain(|then| now.saturating_sub(*then) < period);
+                    self.recent_disputes.len() as u32
+                }
+                _ => self.dispute_count,
+            };
+            if disputed >= limit {
+                return Err(Error::ClientDisputeLimit {
+                    client: self.info.client,
+                    tx: transaction.id(),
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // the part of `remaining` that a dispute, resolve or chargeback row covers, with its sign
+    // a row without amount covers all of it
+    fn covered_amount(
+        &self,
+        transaction: &Transaction,
+        remaining: Amount,
+    ) -> Result<Amount, Error> {
+        let amount = transaction.amount().unwrap_or(remaining.abs());
+        if remaining.abs() < AMOUNT_TOLERANCE || amount > remaining.abs() + AMOUNT_TOLERANCE {
+            return Err(Error::DisputeExceedsAmount {
+                client: self.info.client,
+                tx: transaction.id(),
+                amount,
+                remaining: remaining.abs(),
+            });
+        }
+        Ok(amount.min(remaining.abs()).copysign(remaining))
+    }
+
+    // take the settled part out of the open dispute, it is closed once nothing is held anymore
+    fn settle_dispute(&mut self, tx: TransactionID, amount: Amount) {
+        if let Some(dispute) = self.dispute_transactions.get_mut(&tx) {
+            dispute.amount -= amount;
+            if dispute.amount.abs() < AMOUNT_TOLERANCE {
+                self.dispute_transactions.remove(&tx);
+            }
+        }
+    }
+
     /// dispute a previous transaction. It could be deposit and withdrawal
+    /// the amount of the row holds only part of it, the rest could be disputed later on
     pub fn dispute(&mut self, transaction: &Transaction) -> Result<(), Error> {
-        // find the previous transaction and pop it outof database
-        if let Some((_, (client_id, tx_amount))) = self
+        let record = match self
             .transaction_db
-            .borrow_mut()
-            .pop_transaction(&transaction.id())
+            .borrow()
+            .get_transaction(&transaction.id())
         {
             // check the current client owns the transactions that will be disputed
-            if client_id == self.info.client {
-                self.dispute_transactions
-                    .insert(transaction.id(), tx_amount);
-                self.info.available -= tx_amount;
-                self.info.held += tx_amount;
-            } else {
-                println!("Wrong");
+            Some(record) if record.client == self.info.client => record,
+            _ => {
+                return Err(Error::NotFoundTransaction {
+                    client: self.info.client,
+                    tx: transaction.id(),
+                })
             }
-            Ok(())
-        } else {
-            Err(Error::NotFoundTransaction {
+        };
+        // the withdrawals are recorded as negative amount
+        if record.amount < 0f32 && self.config.withdrawal_dispute == WithdrawalDisputeMode::Forbid {
+            return Err(Error::DisputeNotAllowed {
                 client: self.info.client,
                 tx: transaction.id(),
+            });
+        }
+        // the window is only checked when both rows have a timestamp
+        if let (Some(max_age), Some(then), Some(now)) = (
+            self.config.dispute_window.max_age,
+            record.timestamp,
+            transaction.timestamp(),
+        ) {
+            let age = now.saturating_sub(then);
+            if age > max_age {
+                return Err(Error::DisputeWindowExpired {
+                    client: self.info.client,
+                    tx: transaction.id(),
+                    age,
+                });
+            }
+        }
+        self.check_dispute_limits(transaction)?;
+        let tx_amount = self.covered_amount(transaction, record.outstanding)?;
+        // only a disputed deposit takes the available funds down
+        let below_limit = tx_amount > 0f32
+            && !self.is_held_credit(tx_amount)
+            && self.info.available - tx_amount < -self.overdraft_limit;
+        if below_limit && self.config.overdraft.on_dispute_below_limit == DisputeBelowLimit::Reject
+        {
+            return Err(Error::OverdraftLimitExceeded {
+                client: self.info.client,
+                tx: transaction.id(),
+                available: self.info.available - tx_amount,
+                limit: self.overdraft_limit,
+            });
+        }
+        self.record_dispute_event(transaction, tx_amount);
+        if let Some(record) = self
+            .transaction_db
+            .borrow_mut()
+            .get_transaction_mut(&transaction.id())
+        {
+            record.outstanding -= tx_amount;
+        }
+        // a second dispute of the same transaction adds to the open one
+        // and keeps the reason it was opened with
+        self.dispute_transactions
+            .entry(transaction.id())
+            .or_insert_with(|| OpenDispute {
+                amount: 0f32,
+                opened_at: transaction.timestamp(),
+                reason_code: transaction.reason_code().map(|code| code.to_string()),
+                note: transaction.reason().map(|note| note.to_string()),
             })
+            .amount += tx_amount;
+        if self.is_held_credit(tx_amount) {
+            // the withdrawn amount comes back as held funds
+            self.info.held -= tx_amount;
+            self.info.total -= tx_amount;
+        } else {
+            self.info.available -= tx_amount;
+            self.info.held += tx_amount;
+        }
+        if below_limit
+            && self.config.overdraft.on_dispute_below_limit == DisputeBelowLimit::Freeze
+            && self.info.status == AccountStatus::Active
+        {
+            self.change_status(
+                transaction,
+                AccountStatus::Frozen,
+                Some(format!(
+                    "dispute of transaction {} below the overdraft limit",
+                    transaction.id()
+                )),
+            );
         }
+        Ok(())
     }
 
-    /// resolve a previous dispute and recover the disputed transaction back to record
+    /// resolve a previous dispute, the resolved amount could be disputed again
     pub fn resolve(&mut self, transaction: &Transaction) -> Result<(), Error> {
         // find the previous disputed transaction
-        if let Some(amount) = self.dispute_transactions.remove(&transaction.id()) {
-            self.info.held -= amount;
-            self.info.available += amount;
-            // if the transaction is resolved, add it back to history for a possible future dispute
-            self.transaction_db.borrow_mut().recover_transaction(
-                transaction.id(),
-                self.info.client,
-                amount,
-            );
-            Ok(())
-        } else {
-            Err(Error::NotFoundTransaction {
+        let held = self
+            .dispute_transactions
+            .get(&transaction.id())
+            .map(|dispute| dispute.amount)
+            .ok_or(Error::NotFoundTransaction {
                 client: self.info.client,
                 tx: transaction.id(),
-            })
+            })?;
+        let amount = self.covered_amount(transaction, held)?;
+        self.settle_dispute(transaction.id(), amount);
+        self.record_dispute_event(transaction, amount);
+        if self.is_held_credit(amount) {
+            // the held credit is taken away
+            self.info.held += amount;
+            self.info.total += amount;
+        } else {
+            self.info.held -= amount;
+            self.info.available += amount;
         }
+        // if the transaction is resolved, it is back in the record for a possible future dispute
+        // it keeps its own timestamp, so the dispute window doesn't start over
+        if let Some(record) = self
+            .transaction_db
+            .borrow_mut()
+            .get_transaction_mut(&transaction.id())
+        {
+            record.outstanding += amount;
+        }
+        Ok(())
     }
 
-    /// a solution to dispute and will lock the account
+    /// a solution to dispute and will lock the account, even when only part of it is charged back
     /// chargeback the dispute on withdrawal is kind of ambiguous
-    /// we allow it here which means the client put money back
+    /// we allow it here which means the client gets the money back,
+    /// see `WithdrawalDisputeMode` for how the funds move
     pub fn chargeback(&mut self, transaction: &Transaction) -> Result<(), Error> {
-        if let Some(amount) = self.dispute_transactions.remove(&transaction.id()) {
+        let (held, reason_code) = self
+            .dispute_transactions
+            .get(&transaction.id())
+            .map(|dispute| (dispute.amount, dispute.reason_code.clone()))
+            .ok_or(Error::NotFoundTransaction {
+                client: self.info.client,
+                tx: transaction.id(),
+            })?;
+        if held < 0f32 && !self.config.allow_withdrawal_chargeback {
+            // the dispute stays open, it could still be resolved
+            return Err(Error::ChargebackNotAllowed {
+                client: self.info.client,
+                tx: transaction.id(),
+            });
+        }
+        let amount = self.covered_amount(transaction, held)?;
+        self.settle_dispute(transaction.id(), amount);
+        self.record_dispute_event(transaction, amount);
+        self.chargebacks.push(ChargebackRecord {
+            client: self.info.client,
+            tx: transaction.id(),
+            amount: amount.abs(),
+            reason_code,
+        });
+        if self.is_held_credit(amount) {
+            // the held credit is released to the client
+            self.info.held += amount;
+            self.info.available -= amount;
+        } else {
             self.info.held -= amount;
             self.info.total -= amount;
-            self.info.is_locked = true;
-            // the transaction won't get back to history for future dispute
-            Ok(())
-        } else {
-            Err(Error::NotFoundTransaction {
+        }
+        if self.info.status != AccountStatus::Locked {
+            self.change_status(
+                transaction,
+                AccountStatus::Locked,
+                Some(format!("chargeback of transaction {}", transaction.id())),
+            );
+        }
+        // the charged back amount won't get back to history for future dispute
+        // and the transaction is gone once nothing of it is left
+        if !self.dispute_transactions.contains_key(&transaction.id()) {
+            let mut transaction_db = self.transaction_db.borrow_mut();
+            if matches!(
+                transaction_db.get_transaction(&transaction.id()),
+                Some(record) if record.outstanding.abs() < AMOUNT_TOLERANCE
+            ) {
+                transaction_db.remove_transaction(&transaction.id());
+            }
+        }
+        Ok(())
+    }
+
+    /// settle the dispute of `tx` with the configured action if it has been open
+    /// for longer than `max_open` at `now`. the status policy doesn't apply here
+    /// returns the resolve or chargeback made up for it, or none if the dispute
+    /// isn't due(e.g. it was resolved and opened again later on)
+    pub fn expire_dispute(&mut self, tx: TransactionID, now: Timestamp) -> Option<Transaction> {
+        let max_open = self.config.dispute_window.max_open?;
+        let opened_at = self.dispute_transactions.get(&tx)?.opened_at?;
+        if opened_at.saturating_add(max_open) >= now {
+            return None;
+        }
+        let client = self.info.client;
+        let settle = |t_type| {
+            Transaction::new(t_type, client, tx, None)
+                .with_timestamp(now)
+                .with_reason(&format!("dispute of transaction {} expired", tx))
+        };
+        if self.config.dispute_window.on_expiry == DisputeExpiryAction::Chargeback {
+            let chargeback = settle(TransactionType::Chargeback);
+            if self.chargeback(&chargeback).is_ok() {
+                self.update_debt();
+                return Some(chargeback);
+            }
+        }
+        // a withdrawal that can't be charged back is resolved instead
+        let resolve = settle(TransactionType::Resolve);
+        self.resolve(&resolve).ok().map(|_| resolve)
+    }
+
+    pub fn audit_trail(&self) -> &[AuditEntry] {
+        &self.audit_trail
+    }
+
+    pub fn open_dispute_count(&self) -> usize {
+        self.dispute_transactions.len()
+    }
+
+    /// the statement of the open disputes, ordered by transaction id
+    pub fn open_disputes(&self) -> Vec<DisputeInfo> {
+        let mut disputes: Vec<DisputeInfo> = self
+            .dispute_transactions
+            .iter()
+            .map(|(tx, dispute)| DisputeInfo {
+                client: self.info.client,
+                tx: *tx,
+                amount: dispute.amount.abs(),
+                opened_at: dispute.opened_at,
+                reason_code: dispute.reason_code.clone(),
+                note: dispute.note.clone(),
+            })
+            .collect();
+        disputes.sort_by_key(|dispute| dispute.tx);
+        disputes
+    }
+
+    /// the disputes, resolves and chargebacks of the transaction in the order they happened
+    pub fn dispute_history(&self, tx: TransactionID) -> &[DisputeEvent] {
+        self.dispute_history
+            .get(&tx)
+            .map(|events| events.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn chargebacks(&self) -> &[ChargebackRecord] {
+        &self.chargebacks
+    }
+
+    fn change_status(
+        &mut self,
+        transaction: &Transaction,
+        status: AccountStatus,
+        reason: Option<String>,
+    ) {
+        info!(
+            client = self.info.client,
+            from = %self.info.status,
+            to = %status,
+            reason = reason.as_deref().unwrap_or_default(),
+            "account status changed"
+        );
+        self.audit_trail.push(AuditEntry {
+            client: self.info.client,
+            tx: transaction.id(),
+            from: self.info.status,
+            to: status,
+            reason,
+        });
+        self.info.status = status;
+    }
+
+    // the administrative operation is only allowed from some of the status
+    fn admin_operation(
+        &mut self,
+        transaction: &Transaction,
+        allowed_from: &[AccountStatus],
+        status: AccountStatus,
+    ) -> Result<(), Error> {
+        if !allowed_from.contains(&self.info.status) {
+            return Err(Error::InvalidStatusChange {
+                client: self.info.client,
+                from: self.info.status,
+                to: status,
+            });
+        }
+        let reason = transaction.reason().map(|reason| reason.to_string());
+        self.change_status(transaction, status, reason);
+        Ok(())
+    }
+
+    /// freeze the account on behalf of a risk rule, nothing changes unless it is active
+    /// returns whether the account was frozen
+    pub fn freeze_by_rule(&mut self, transaction: &Transaction, reason: String) -> bool {
+        let active = self.info.status == AccountStatus::Active;
+        if active {
+            self.change_status(transaction, AccountStatus::Frozen, Some(reason));
+        }
+        active
+    }
+
+    /// freeze the account, only the withdrawals are blocked
+    pub fn freeze(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        self.admin_operation(transaction, &[AccountStatus::Active], AccountStatus::Frozen)
+    }
+
+    /// lock the account, every transaction is blocked
+    pub fn lock(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        self.admin_operation(
+            transaction,
+            &[AccountStatus::Active, AccountStatus::Frozen],
+            AccountStatus::Locked,
+        )
+    }
+
+    /// bring a frozen or locked account back to active, the reason is required
+    pub fn unlock(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        if transaction.reason().is_none() {
+            return Err(Error::MissingReason {
+                client: self.info.client,
+                tx: transaction.id(),
+            });
+        }
+        self.admin_operation(
+            transaction,
+            &[AccountStatus::Frozen, AccountStatus::Locked],
+            AccountStatus::Active,
+        )
+    }
+
+    /// close the account for good, it must not hold any funds or open dispute
+    pub fn close(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        // the rounding errors of the deposits and withdrawals don't keep an account open
+        if self.info.total.abs() >= AMOUNT_TOLERANCE
+            || self.info.held.abs() >= AMOUNT_TOLERANCE
+            || !self.dispute_transactions.is_empty()
+        {
+            return Err(Error::AccountNotEmpty {
+                client: self.info.client,
+                total: self.info.total,
+            });
+        }
+        self.admin_operation(
+            transaction,
+            &[
+                AccountStatus::Active,
+                AccountStatus::Frozen,
+                AccountStatus::Locked,
+            ],
+            AccountStatus::Closed,
+        )
+    }
+
+    /// set the overdraft limit of the account to the amount of the row
+    /// only an active account gets a new credit line, the change goes to the audit trail
+    pub fn overdraft(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        if self.info.status != AccountStatus::Active {
+            return Err(self.status_error());
+        }
+        if let Some(limit) = transaction.amount() {
+            // the status stays as it is, the entry only records the new limit
+            self.audit_trail.push(AuditEntry {
                 client: self.info.client,
                 tx: transaction.id(),
+                from: self.info.status,
+                to: self.info.status,
+                reason: Some(format!(
+                    "overdraft limit {} -> {}",
+                    format_amount(self.overdraft_limit, self.config.precision),
+                    format_amount(limit, self.config.precision)
+                )),
+            });
+            self.overdraft_limit = limit;
+        }
+        Ok(())
+    }
+
+    pub fn overdraft_limit(&self) -> Amount {
+        self.overdraft_limit
+    }
+
+    // the funds held by the open disputes, a disputed withdrawal takes them down
+    // unless it is held as credit
+    pub(crate) fn disputed_funds(&self) -> Amount {
+        self.dispute_transactions
+            .values()
+            .map(|dispute| {
+                if self.is_held_credit(dispute.amount) {
+                    -dispute.amount
+                } else {
+                    dispute.amount
+                }
             })
+            .sum()
+    }
+
+    // what is left of `tx` to dispute, signed like the transaction, none unless it is of this client
+    pub(crate) fn outstanding(&self, tx: TransactionID) -> Option<Amount> {
+        match self.transaction_db.borrow().get_transaction(&tx) {
+            Some(record) if record.client == self.info.client => Some(record.outstanding),
+            _ => None,
+        }
+    }
+
+    // what the open dispute of `tx` still holds, signed like the transaction
+    pub(crate) fn open_dispute_amount(&self, tx: TransactionID) -> Option<Amount> {
+        self.dispute_transactions
+            .get(&tx)
+            .map(|dispute| dispute.amount)
+    }
+
+    // whether a dispute of `tx` credits the withdrawn amount as held funds
+    pub(crate) fn holds_credit(&self, tx: TransactionID) -> bool {
+        matches!(
+            self.transaction_db.borrow().get_transaction(&tx),
+            Some(record) if record.client == self.info.client && self.is_held_credit(record.amount)
+        )
+    }
+
+    // look for the debt after every transaction
+    // the disputes don't change the total, so the held funds never count as debt. a negative
+    // available, e.g. a disputed deposit that was already withdrawn, isn't owed until the
+    // chargeback, it only shows up among the overdrawn accounts
+    fn update_debt(&mut self) {
+        let shortfall = -(self.info.total + self.overdraft_limit);
+        if !self.config.debt.repay_first {
+            self.info.debt = shortfall.max(0f32);
+        } else if shortfall > 0f32 {
+            self.info.debt += shortfall;
+            self.info.available += shortfall;
+            self.info.total += shortfall;
         }
     }
 
     pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), Error> {
         self.validate_transaction(transaction)?;
+        trace!("validated");
+        self.dispatch(transaction)?;
+        self.update_debt();
+        Ok(())
+    }
+
+    fn dispatch(&mut self, transaction: &Transaction) -> Result<(), Error> {
         match transaction.t_type() {
             TransactionType::Deposit => self.deposit(transaction),
             TransactionType::Withdrawal => self.withdrawal(transaction),
             TransactionType::Dispute => self.dispute(transaction),
             TransactionType::Resolve => self.resolve(transaction),
             TransactionType::Chargeback => self.chargeback(transaction),
+            TransactionType::Freeze => self.freeze(transaction),
+            TransactionType::Lock => self.lock(transaction),
+            TransactionType::Unlock => self.unlock(transaction),
+            TransactionType::Close => self.close(transaction),
+            TransactionType::Overdraft => self.overdraft(transaction),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{ClientAccount, ClientAccountInfo, Error, Transaction};
+    use crate::{
+        AccountStatus, ClientAccount, ClientAccountInfo, DisputeBelowLimit, EngineConfig, Error,
+        Transaction, TransactionHashmapDB, TransactionType, WithdrawalDisputeMode,
+    };
+    use std::{cell::RefCell, rc::Rc};
+
+    fn account_with_config(config: EngineConfig) -> ClientAccount {
+        ClientAccount::new_with_config(
+            0,
+            Rc::new(RefCell::new(TransactionHashmapDB::new())),
+            Rc::new(config),
+        )
+    }
+
+    // a withdrawal of 2.0 from an account of 10.0
+    fn account_after_withdrawal(config: EngineConfig) -> ClientAccount {
+        let mut account = account_with_config(config);
+        account.info.available = 10f32;
+        account.info.total = 10f32;
+        let withdrawal = Transaction::new(crate::TransactionType::Withdrawal, 0, 0, Some(2f32));
+        account.process_transaction(&withdrawal).unwrap();
+        account
+    }
 
     #[test]
     fn test_serialize_client_account_info() {
@@ -236,7 +885,7 @@ mod tests {
         let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(
             data,
-            "client,available,held,total,locked\n0,0.0,0.0,0.0,false\n"
+            "client,available,held,total,debt,status\n0,0.0000,0.0000,0.0000,0.0000,active\n"
         );
     }
 
@@ -246,7 +895,7 @@ mod tests {
         account_info.available = 0.123;
         account_info.held = 0.1234;
         account_info.total = 0.12345;
-        account_info.is_locked = true;
+        account_info.status = AccountStatus::Locked;
         let mut writer = csv::WriterBuilder::new()
             .delimiter(b',')
             .from_writer(vec![]);
@@ -254,7 +903,7 @@ mod tests {
         let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(
             data,
-            "client,available,held,total,locked\n0,0.123,0.1234,0.1235,true\n"
+            "client,available,held,total,debt,status\n0,0.1230,0.1234,0.1235,0.0000,locked\n"
         );
     }
 
@@ -370,8 +1019,11 @@ mod tests {
             account.info.available + account.info.held
         );
         assert_eq!(account.info.held, amount);
-        assert!(account.dispute_transactions.len() > 0);
-        assert_eq!(account.dispute_transactions.get(&0), Some(&amount));
+        assert!(!account.dispute_transactions.is_empty());
+        assert_eq!(
+            account.dispute_transactions.get(&0).map(|d| d.amount),
+            Some(amount)
+        );
     }
 
     #[test]
@@ -409,8 +1061,11 @@ mod tests {
             account.info.available + account.info.held
         );
         assert_eq!(account.info.held, -amount);
-        assert!(account.dispute_transactions.len() > 0);
-        assert_eq!(account.dispute_transactions.get(&0), Some(&-amount));
+        assert!(!account.dispute_transactions.is_empty());
+        assert_eq!(
+            account.dispute_transactions.get(&0).map(|d| d.amount),
+            Some(-amount)
+        );
     }
 
     #[test]
@@ -460,8 +1115,11 @@ mod tests {
             account.info.available + account.info.held
         );
         assert_eq!(account.info.held, amount);
-        assert!(account.dispute_transactions.len() > 0);
-        assert_eq!(account.dispute_transactions.get(&0), Some(&amount));
+        assert!(!account.dispute_transactions.is_empty());
+        assert_eq!(
+            account.dispute_transactions.get(&0).map(|d| d.amount),
+            Some(amount)
+        );
 
         let resolve = Transaction::new(
             crate::TransactionType::Resolve,
@@ -477,12 +1135,15 @@ mod tests {
         );
         assert_eq!(account.info.held, 0f32);
         assert!(account.dispute_transactions.is_empty());
-        //assert!(account.history_transactions.contains_key(&0)); // recover the transaction back to record
-        assert!(account
-            .transaction_db
-            .borrow_mut()
-            .pop_transaction(&0)
-            .is_some());
+        // the transaction is back in the record for a possible future dispute
+        assert_eq!(
+            account
+                .transaction_db
+                .borrow()
+                .get_transaction(&0)
+                .map(|record| record.outstanding),
+            Some(amount)
+        );
     }
 
     #[test]
@@ -517,8 +1178,11 @@ mod tests {
             account.info.available + account.info.held
         );
         assert_eq!(account.info.held, amount);
-        assert!(account.dispute_transactions.len() > 0);
-        assert_eq!(account.dispute_transactions.get(&0), Some(&amount));
+        assert!(!account.dispute_transactions.is_empty());
+        assert_eq!(
+            account.dispute_transactions.get(&0).map(|d| d.amount),
+            Some(amount)
+        );
 
         let chargeback = Transaction::new(
             crate::TransactionType::Chargeback,
@@ -535,4 +1199,684 @@ mod tests {
         assert_eq!(account.info.held, 0.0);
         assert!(account.dispute_transactions.is_empty());
     }
+
+    #[test]
+    fn test_policy_withdrawal_dispute() {
+        let dispute = Transaction::new(crate::TransactionType::Dispute, 0, 0, None);
+        let mut account = account_after_withdrawal(EngineConfig {
+            withdrawal_dispute: WithdrawalDisputeMode::Forbid,
+            ..EngineConfig::default()
+        });
+        let result = account.process_transaction(&dispute);
+        assert!(matches!(result, Err(Error::DisputeNotAllowed { .. })));
+        assert_eq!(account.info.available, 8f32);
+        assert!(account.dispute_transactions.is_empty());
+
+        let mut account = account_after_withdrawal(EngineConfig::default());
+        account.process_transaction(&dispute).unwrap();
+        assert_eq!(account.info.available, 10f32);
+    }
+
+    #[test]
+    fn test_policy_withdrawal_chargeback() {
+        let dispute = Transaction::new(crate::TransactionType::Dispute, 0, 0, None);
+        let chargeback = Transaction::new(crate::TransactionType::Chargeback, 0, 0, None);
+        let mut account = account_after_withdrawal(EngineConfig {
+            allow_withdrawal_chargeback: false,
+            ..EngineConfig::default()
+        });
+        account.process_transaction(&dispute).unwrap();
+        let result = account.process_transaction(&chargeback);
+        assert!(matches!(result, Err(Error::ChargebackNotAllowed { .. })));
+        assert!(!account.info.is_locked());
+        assert_eq!(
+            account.dispute_transactions.get(&0).map(|d| d.amount),
+            Some(-2f32)
+        );
+
+        let mut account = account_after_withdrawal(EngineConfig::default());
+        account.process_transaction(&dispute).unwrap();
+        account.process_transaction(&chargeback).unwrap();
+        assert!(account.info.is_locked());
+        assert_eq!(account.info.total, 10f32);
+    }
+
+    #[test]
+    fn test_policy_withdrawal_requires_total() {
+        // the available funds cover the withdrawal, but the total doesn't
+        let withdrawal = Transaction::new(crate::TransactionType::Withdrawal, 0, 1, Some(5f32));
+        let mut account = account_with_config(EngineConfig::default());
+        account.info.available = 5f32;
+        account.info.held = -2f32;
+        account.info.total = 3f32;
+        let result = account.process_transaction(&withdrawal);
+        assert!(matches!(result, Err(Error::NoSufficientFunds { .. })));
+
+        let mut account = account_with_config(EngineConfig {
+            withdrawal_requires_total: false,
+            ..EngineConfig::default()
+        });
+        account.info.available = 5f32;
+        account.info.held = -2f32;
+        account.info.total = 3f32;
+        account.process_transaction(&withdrawal).unwrap();
+        assert_eq!(account.info.available, 0f32);
+        assert_eq!(account.info.total, -2f32);
+    }
+
+    // (available, held, total, locked) after each step
+    type Balance = (f32, f32, f32, bool);
+
+    fn balance(account: &ClientAccount) -> Balance {
+        (
+            account.info.available,
+            account.info.held,
+            account.info.total,
+            account.info.is_locked(),
+        )
+    }
+
+    // withdraw 2.0 from 10.0, dispute it, then finish the dispute with `last`
+    fn run_withdrawal_dispute(
+        mode: WithdrawalDisputeMode,
+        last: TransactionType,
+    ) -> (Result<(), Error>, Balance, Result<(), Error>, Balance) {
+        let mut account = account_after_withdrawal(EngineConfig {
+            withdrawal_dispute: mode,
+            ..EngineConfig::default()
+        });
+        let dispute =
+            account.process_transaction(&Transaction::new(TransactionType::Dispute, 0, 0, None));
+        let after_dispute = balance(&account);
+        let finish = account.process_transaction(&Transaction::new(last, 0, 0, None));
+        (dispute, after_dispute, finish, balance(&account))
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_modes() {
+        use TransactionType::{Chargeback, Resolve};
+        use WithdrawalDisputeMode::{Forbid, HeldCredit, Reversal};
+        let start = (8f32, 0f32, 8f32, false);
+
+        // mode, dispute and resolve, balances after dispute and after resolve, after chargeback
+        let matrix: [(WithdrawalDisputeMode, bool, Balance, Balance, Balance); 3] = [
+            (Forbid, false, start, start, start),
+            (
+                Reversal,
+                true,
+                (10f32, -2f32, 8f32, false),
+                start,
+                (10f32, 0f32, 10f32, true),
+            ),
+            (
+                HeldCredit,
+                true,
+                (8f32, 2f32, 10f32, false),
+                start,
+                (10f32, 0f32, 10f32, true),
+            ),
+        ];
+        for (mode, allowed, after_dispute, after_resolve, after_chargeback) in matrix {
+            let (dispute, disputed, resolve, resolved) = run_withdrawal_dispute(mode, Resolve);
+            assert_eq!(dispute.is_ok(), allowed, "{:?}", mode);
+            assert_eq!(disputed, after_dispute, "{:?}", mode);
+            assert_eq!(resolve.is_ok(), allowed, "{:?}", mode);
+            assert_eq!(resolved, after_resolve, "{:?}", mode);
+            if !allowed {
+                assert!(matches!(dispute, Err(Error::DisputeNotAllowed { .. })));
+                assert!(matches!(resolve, Err(Error::NotFoundTransaction { .. })));
+            }
+
+            let (_, _, chargeback, charged_back) = run_withdrawal_dispute(mode, Chargeback);
+            assert_eq!(chargeback.is_ok(), allowed, "{:?}", mode);
+            assert_eq!(charged_back, after_chargeback, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_deposit_dispute_is_the_same_in_every_mode() {
+        for mode in [
+            WithdrawalDisputeMode::Forbid,
+            WithdrawalDisputeMode::Reversal,
+            WithdrawalDisputeMode::HeldCredit,
+        ] {
+            let mut account = account_with_config(EngineConfig {
+                withdrawal_dispute: mode,
+                ..EngineConfig::default()
+            });
+            let deposit = Transaction::new(TransactionType::Deposit, 0, 0, Some(2f32));
+            let dispute = Transaction::new(TransactionType::Dispute, 0, 0, None);
+            let chargeback = Transaction::new(TransactionType::Chargeback, 0, 0, None);
+            account.process_transaction(&deposit).unwrap();
+            account.process_transaction(&dispute).unwrap();
+            assert_eq!(balance(&account), (0f32, 2f32, 2f32, false));
+            account.process_transaction(&chargeback).unwrap();
+            assert_eq!(balance(&account), (0f32, 0f32, 0f32, true));
+        }
+    }
+
+    fn admin(t_type: TransactionType, tx: u32, reason: Option<&str>) -> Transaction {
+        let transaction = Transaction::new(t_type, 0, tx, None);
+        match reason {
+            Some(reason) => transaction.with_reason(reason),
+            None => transaction,
+        }
+    }
+
+    #[test]
+    fn test_freeze_blocks_withdrawal_only() {
+        let mut account = ClientAccount::new(0);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 1, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        account
+            .process_transaction(&admin(TransactionType::Freeze, 2, Some("kyc")))
+            .unwrap();
+        assert_eq!(account.info.status, AccountStatus::Frozen);
+
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 3, Some(1f32));
+        let result = account.process_transaction(&withdrawal);
+        assert!(matches!(result, Err(Error::AccountFrozen { .. })));
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 4, Some(1f32));
+        account.process_transaction(&deposit).unwrap();
+        let dispute = Transaction::new(TransactionType::Dispute, 0, 1, None);
+        account.process_transaction(&dispute).unwrap();
+        assert_eq!(balance(&account), (1f32, 5f32, 6f32, false));
+
+        // freezing twice is an error
+        let result = account.process_transaction(&admin(TransactionType::Freeze, 5, None));
+        assert!(matches!(result, Err(Error::InvalidStatusChange { .. })));
+    }
+
+    #[test]
+    fn test_unlock_needs_a_reason() {
+        let mut account = ClientAccount::new(0);
+        account
+            .process_transaction(&admin(TransactionType::Lock, 1, None))
+            .unwrap();
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 2, Some(5f32));
+        let result = account.process_transaction(&deposit);
+        assert!(matches!(result, Err(Error::AccountLocked { .. })));
+
+        let result = account.process_transaction(&admin(TransactionType::Unlock, 3, None));
+        assert!(matches!(result, Err(Error::MissingReason { .. })));
+        assert!(account.info.is_locked());
+        account
+            .process_transaction(&admin(TransactionType::Unlock, 4, Some("false alarm")))
+            .unwrap();
+        assert_eq!(account.info.status, AccountStatus::Active);
+        account.process_transaction(&deposit).unwrap();
+
+        // unlocking an active account is an error
+        let result = account.process_transaction(&admin(TransactionType::Unlock, 5, Some("again")));
+        assert!(matches!(result, Err(Error::InvalidStatusChange { .. })));
+    }
+
+    #[test]
+    fn test_close_needs_zero_balance() {
+        let mut account = ClientAccount::new(0);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 1, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        let result = account.process_transaction(&admin(TransactionType::Close, 2, None));
+        assert!(matches!(result, Err(Error::AccountNotEmpty { .. })));
+
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 3, Some(5f32));
+        account.process_transaction(&withdrawal).unwrap();
+        account
+            .process_transaction(&admin(TransactionType::Close, 4, Some("moved away")))
+            .unwrap();
+        assert_eq!(account.info.status, AccountStatus::Closed);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 5, Some(5f32));
+        let result = account.process_transaction(&deposit);
+        assert!(matches!(result, Err(Error::AccountClosed { .. })));
+        let result =
+            account.process_transaction(&admin(TransactionType::Unlock, 6, Some("reopen")));
+        assert!(matches!(result, Err(Error::InvalidStatusChange { .. })));
+
+        // 1.1 + 2.2 - 3.3 isn't exactly 0 in f32
+        let mut account = ClientAccount::new(1);
+        for (t_type, tx, amount) in [
+            (TransactionType::Deposit, 1, 1.1f32),
+            (TransactionType::Deposit, 2, 2.2f32),
+            (TransactionType::Withdrawal, 3, 3.3f32),
+        ] {
+            account
+                .process_transaction(&Transaction::new(t_type, 1, tx, Some(amount)))
+                .unwrap();
+        }
+        assert_ne!(account.info.total, 0f32);
+        account
+            .process_transaction(&Transaction::new(TransactionType::Close, 1, 4, None))
+            .unwrap();
+        assert_eq!(account.info.status, AccountStatus::Closed);
+    }
+
+    #[test]
+    fn test_audit_trail() {
+        let mut account = ClientAccount::new(0);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 1, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        account
+            .process_transaction(&admin(TransactionType::Freeze, 2, Some("kyc")))
+            .unwrap();
+        let dispute = Transaction::new(TransactionType::Dispute, 0, 1, None);
+        let chargeback = Transaction::new(TransactionType::Chargeback, 0, 1, None);
+        account.process_transaction(&dispute).unwrap();
+        account.process_transaction(&chargeback).unwrap();
+        account
+            .process_transaction(&admin(TransactionType::Unlock, 3, Some("refunded")))
+            .unwrap();
+
+        let trail: Vec<(u32, AccountStatus, AccountStatus, Option<&str>)> = account
+            .audit_trail()
+            .iter()
+            .map(|entry| (entry.tx, entry.from, entry.to, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            trail,
+            vec![
+                (2, AccountStatus::Active, AccountStatus::Frozen, Some("kyc")),
+                (
+                    1,
+                    AccountStatus::Frozen,
+                    AccountStatus::Locked,
+                    Some("chargeback of transaction 1")
+                ),
+                (
+                    3,
+                    AccountStatus::Locked,
+                    AccountStatus::Active,
+                    Some("refunded")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_open_dispute_finishes_on_locked_account() {
+        let mut config = EngineConfig::default();
+        config.status_policy.locked = vec![TransactionType::Resolve, TransactionType::Chargeback];
+        let mut account = account_with_config(config);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 1, Some(5f32));
+        let dispute = Transaction::new(TransactionType::Dispute, 0, 1, None);
+        account.process_transaction(&deposit).unwrap();
+        account.process_transaction(&dispute).unwrap();
+        account
+            .process_transaction(&admin(TransactionType::Lock, 2, None))
+            .unwrap();
+
+        let result = account.process_transaction(&deposit);
+        assert!(matches!(result, Err(Error::AccountLocked { .. })));
+        let resolve = Transaction::new(TransactionType::Resolve, 0, 1, None);
+        account.process_transaction(&resolve).unwrap();
+        assert_eq!(balance(&account), (5f32, 0f32, 5f32, true));
+        // but a new dispute can't be opened
+        let result = account.process_transaction(&dispute);
+        assert!(matches!(result, Err(Error::AccountLocked { .. })));
+    }
+
+    #[test]
+    fn test_locked_account_takes_deposits_only() {
+        let mut config = EngineConfig::default();
+        config.status_policy.locked = vec![TransactionType::Deposit];
+        let mut account = account_with_config(config);
+        account
+            .process_transaction(&admin(TransactionType::Lock, 1, None))
+            .unwrap();
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 2, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 3, Some(1f32));
+        let result = account.process_transaction(&withdrawal);
+        assert!(matches!(result, Err(Error::AccountLocked { .. })));
+        assert_eq!(balance(&account), (5f32, 0f32, 5f32, true));
+    }
+
+    fn dispute_row(t_type: TransactionType, amount: Option<f32>) -> Transaction {
+        Transaction::new(t_type, 0, 1, amount)
+    }
+
+    #[test]
+    fn test_partial_dispute_and_resolve() {
+        let mut account = ClientAccount::new(0);
+        account
+            .process_transaction(&dispute_row(TransactionType::Deposit, Some(10f32)))
+            .unwrap();
+        account
+            .process_transaction(&dispute_row(TransactionType::Dispute, Some(4f32)))
+            .unwrap();
+        assert_eq!(balance(&account), (6f32, 4f32, 10f32, false));
+        // the rest could be disputed on top, but not more
+        let result =
+            account.process_transaction(&dispute_row(TransactionType::Dispute, Some(7f32)));
+        assert!(matches!(
+            result,
+            Err(Error::DisputeExceedsAmount {
+                amount,
+                remaining,
+                ..
+            }) if amount == 7f32 && remaining == 6f32
+        ));
+        account
+            .process_transaction(&dispute_row(TransactionType::Dispute, Some(6f32)))
+            .unwrap();
+        assert_eq!(balance(&account), (0f32, 10f32, 10f32, false));
+        let result = account.process_transaction(&dispute_row(TransactionType::Dispute, None));
+        assert!(matches!(result, Err(Error::DisputeExceedsAmount { .. })));
+
+        // a partial resolve keeps the dispute open and makes the amount disputable again
+        account
+            .process_transaction(&dispute_row(TransactionType::Resolve, Some(3f32)))
+            .unwrap();
+        assert_eq!(balance(&account), (3f32, 7f32, 10f32, false));
+        assert_eq!(account.dispute_transactions.get(&1).unwrap().amount, 7f32);
+        account
+            .process_transaction(&dispute_row(TransactionType::Resolve, None))
+            .unwrap();
+        assert_eq!(balance(&account), (10f32, 0f32, 10f32, false));
+        assert!(account.dispute_transactions.is_empty());
+        let record = account.transaction_db.borrow().get_transaction(&1).unwrap();
+        assert_eq!(record.outstanding, 10f32);
+    }
+
+    #[test]
+    fn test_partial_chargeback() {
+        let mut account = ClientAccount::new(0);
+        account
+            .process_transaction(&dispute_row(TransactionType::Deposit, Some(10f32)))
+            .unwrap();
+        account
+            .process_transaction(&dispute_row(TransactionType::Dispute, Some(4f32)))
+            .unwrap();
+        let result =
+            account.process_transaction(&dispute_row(TransactionType::Chargeback, Some(5f32)));
+        assert!(matches!(result, Err(Error::DisputeExceedsAmount { .. })));
+        account
+            .process_transaction(&dispute_row(TransactionType::Chargeback, Some(1f32)))
+            .unwrap();
+        // the account is locked by the partial chargeback, the rest stays held
+        assert_eq!(balance(&account), (6f32, 3f32, 9f32, true));
+        assert_eq!(account.dispute_transactions.get(&1).unwrap().amount, 3f32);
+        let record = account.transaction_db.borrow().get_transaction(&1).unwrap();
+        assert_eq!((record.amount, record.outstanding), (10f32, 6f32));
+    }
+
+    #[test]
+    fn test_invalid_dispute_amount() {
+        let mut account = ClientAccount::new(0);
+        account
+            .process_transaction(&dispute_row(TransactionType::Deposit, Some(10f32)))
+            .unwrap();
+        for amount in [0f32, -1f32, f32::NAN] {
+            let result =
+                account.process_transaction(&dispute_row(TransactionType::Dispute, Some(amount)));
+            assert!(matches!(result, Err(Error::InvalidTransaction { .. })));
+        }
+    }
+
+    #[test]
+    fn test_dispute_of_another_client() {
+        let db = Rc::new(RefCell::new(TransactionHashmapDB::new()));
+        let mut owner = ClientAccount::new_with_db(1, db.clone());
+        let mut other = ClientAccount::new_with_db(2, db);
+        owner
+            .process_transaction(&Transaction::new(
+                TransactionType::Deposit,
+                1,
+                1,
+                Some(5f32),
+            ))
+            .unwrap();
+        let result =
+            other.process_transaction(&Transaction::new(TransactionType::Dispute, 2, 1, None));
+        assert!(matches!(
+            result,
+            Err(Error::NotFoundTransaction { client: 2, tx: 1 })
+        ));
+        // the transaction is left alone, its owner could still dispute it
+        owner
+            .process_transaction(&Transaction::new(TransactionType::Dispute, 1, 1, None))
+            .unwrap();
+        assert_eq!(owner.info.held, 5f32);
+    }
+
+    #[test]
+    fn test_dispute_limit_per_transaction() {
+        let mut config = EngineConfig::default();
+        config.dispute_limits.max_per_transaction = Some(2);
+        let mut account = account_with_config(config);
+        account
+            .process_transaction(&dispute_row(TransactionType::Deposit, Some(10f32)))
+            .unwrap();
+        // the dispute and resolve loop ends at the limit
+        for _ in 0..2 {
+            account
+                .process_transaction(&dispute_row(TransactionType::Dispute, None))
+                .unwrap();
+            account
+                .process_transaction(&dispute_row(TransactionType::Resolve, None))
+                .unwrap();
+        }
+        let result = account.process_transaction(&dispute_row(TransactionType::Dispute, None));
+        assert!(matches!(
+            result,
+            Err(Error::TransactionDisputeLimit {
+                tx: 1,
+                limit: 2,
+                ..
+            })
+        ));
+        assert_eq!(balance(&account), (10f32, 0f32, 10f32, false));
+        let history: Vec<TransactionType> = account
+            .dispute_history(1)
+            .iter()
+            .map(|event| event.t_type)
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                TransactionType::Dispute,
+                TransactionType::Resolve,
+                TransactionType::Dispute,
+                TransactionType::Resolve
+            ]
+        );
+        assert!(account.dispute_history(2).is_empty());
+
+        // every partial dispute counts
+        let mut config = EngineConfig::default();
+        config.dispute_limits.max_per_transaction = Some(2);
+        let mut account = account_with_config(config);
+        account
+            .process_transaction(&dispute_row(TransactionType::Deposit, Some(10f32)))
+            .unwrap();
+        for _ in 0..2 {
+            account
+                .process_transaction(&dispute_row(TransactionType::Dispute, Some(3f32)))
+                .unwrap();
+        }
+        let result =
+            account.process_transaction(&dispute_row(TransactionType::Dispute, Some(3f32)));
+        assert!(matches!(result, Err(Error::TransactionDisputeLimit { .. })));
+    }
+
+    #[test]
+    fn test_dispute_limit_per_client_and_period() {
+        let mut config = EngineConfig::default();
+        config.dispute_limits.max_per_client = Some(2);
+        config.dispute_limits.period = Some(100);
+        let mut account = account_with_config(config);
+        for tx in 1..=4 {
+            let deposit = Transaction::new(TransactionType::Deposit, 0, tx, Some(1f32));
+            account.process_transaction(&deposit).unwrap();
+        }
+        let dispute = |tx, timestamp| {
+            Transaction::new(TransactionType::Dispute, 0, tx, None).with_timestamp(timestamp)
+        };
+        account.process_transaction(&dispute(1, 0)).unwrap();
+        account.process_transaction(&dispute(2, 50)).unwrap();
+        let result = account.process_transaction(&dispute(3, 99));
+        assert!(matches!(
+            result,
+            Err(Error::ClientDisputeLimit {
+                tx: 3,
+                limit: 2,
+                ..
+            })
+        ));
+        // the first dispute is out of the period now
+        account.process_transaction(&dispute(3, 100)).unwrap();
+        let result = account.process_transaction(&dispute(4, 120));
+        assert!(matches!(result, Err(Error::ClientDisputeLimit { .. })));
+
+        // a dispute that comes out of order leaves the period in its own time
+        let mut config = EngineConfig::default();
+        config.dispute_limits.max_per_client = Some(2);
+        config.dispute_limits.period = Some(100);
+        let mut account = account_with_config(config);
+        for tx in 1..=3 {
+            let deposit = Transaction::new(TransactionType::Deposit, 0, tx, Some(1f32));
+            account.process_transaction(&deposit).unwrap();
+        }
+        account.process_transaction(&dispute(1, 200)).unwrap();
+        account.process_transaction(&dispute(2, 50)).unwrap();
+        account.process_transaction(&dispute(3, 160)).unwrap();
+    }
+
+    fn account_with_overdraft(on_dispute_below_limit: DisputeBelowLimit) -> ClientAccount {
+        let mut config = EngineConfig::default();
+        config.overdraft.limit = 5f32;
+        config.overdraft.on_dispute_below_limit = on_dispute_below_limit;
+        let mut account = account_with_config(config);
+        // 10 in, 12 out, -2 available
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 1, Some(10f32));
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 2, Some(12f32));
+        account.process_transaction(&deposit).unwrap();
+        account.process_transaction(&withdrawal).unwrap();
+        account
+    }
+
+    #[test]
+    fn test_withdrawal_down_to_overdraft_limit() {
+        let mut account = account_with_overdraft(DisputeBelowLimit::Allow);
+        assert_eq!(balance(&account), (-2f32, 0f32, -2f32, false));
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 3, Some(3.5f32));
+        let result = account.process_transaction(&withdrawal);
+        assert!(matches!(result, Err(Error::NoSufficientFunds { .. })));
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 3, Some(3f32));
+        account.process_transaction(&withdrawal).unwrap();
+        assert_eq!(balance(&account), (-5f32, 0f32, -5f32, false));
+
+        // a lower limit doesn't take anything back, but nothing more goes out
+        let overdraft = Transaction::new(TransactionType::Overdraft, 0, 4, Some(1f32));
+        account.process_transaction(&overdraft).unwrap();
+        assert_eq!(account.overdraft_limit(), 1f32);
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 0, 5, Some(0.5f32));
+        let result = account.process_transaction(&withdrawal);
+        assert!(matches!(result, Err(Error::NoSufficientFunds { .. })));
+        let overdraft = Transaction::new(TransactionType::Overdraft, 0, 6, None);
+        let result = account.process_transaction(&overdraft);
+        assert!(matches!(result, Err(Error::InvalidTransaction { .. })));
+        for limit in [f32::INFINITY, f32::NAN] {
+            let overdraft = Transaction::new(TransactionType::Overdraft, 0, 7, Some(limit));
+            let result = account.process_transaction(&overdraft);
+            assert!(matches!(result, Err(Error::InvalidTransaction { .. })));
+        }
+        assert_eq!(account.overdraft_limit(), 1f32);
+        // the new limit is in the audit trail, the status is left alone
+        let entry = account.audit_trail().last().unwrap();
+        assert_eq!(
+            (entry.tx, entry.from, entry.to),
+            (4, AccountStatus::Active, AccountStatus::Active)
+        );
+        assert_eq!(
+            entry.reason.as_deref(),
+            Some("overdraft limit 5.0000 -> 1.0000")
+        );
+
+        // no new credit line for an account that isn't active
+        account.info.status = AccountStatus::Locked;
+        let overdraft = Transaction::new(TransactionType::Overdraft, 0, 8, Some(500f32));
+        let result = account.process_transaction(&overdraft);
+        assert!(matches!(result, Err(Error::AccountLocked { client: 0 })));
+        assert_eq!(account.overdraft_limit(), 1f32);
+    }
+
+    #[test]
+    fn test_dispute_below_overdraft_limit() {
+        let dispute = Transaction::new(TransactionType::Dispute, 0, 1, Some(4f32));
+
+        // -2 - 4 is below -5
+        let mut account = account_with_overdraft(DisputeBelowLimit::Reject);
+        let result = account.process_transaction(&dispute);
+        assert!(matches!(
+            result,
+            Err(Error::OverdraftLimitExceeded { available, limit, .. }) if available == -6f32 && limit == 5f32
+        ));
+        assert_eq!(balance(&account), (-2f32, 0f32, -2f32, false));
+        // a smaller part still fits
+        let dispute_part = Transaction::new(TransactionType::Dispute, 0, 1, Some(3f32));
+        account.process_transaction(&dispute_part).unwrap();
+
+        let mut account = account_with_overdraft(DisputeBelowLimit::Freeze);
+        account.process_transaction(&dispute).unwrap();
+        assert_eq!(balance(&account), (-6f32, 4f32, -2f32, false));
+        assert_eq!(account.info.status, AccountStatus::Frozen);
+        assert_eq!(account.audit_trail().len(), 1);
+
+        let mut account = account_with_overdraft(DisputeBelowLimit::Allow);
+        account.process_transaction(&dispute).unwrap();
+        assert_eq!(balance(&account), (-6f32, 4f32, -2f32, false));
+        assert_eq!(account.info.status, AccountStatus::Active);
+    }
+
+    // 10 in, 8 out, and the deposit is charged back: the account owes 8
+    // the deposits are still allowed on the locked account to pay it back
+    fn account_in_debt(repay_first: bool) -> ClientAccount {
+        let mut config = EngineConfig::default();
+        config.debt.repay_first = repay_first;
+        config.status_policy.locked = vec![TransactionType::Deposit];
+        let mut account = account_with_config(config);
+        for transaction in [
+            Transaction::new(TransactionType::Deposit, 0, 1, Some(10f32)),
+            Transaction::new(TransactionType::Withdrawal, 0, 2, Some(8f32)),
+            Transaction::new(TransactionType::Dispute, 0, 1, None),
+        ] {
+            account.process_transaction(&transaction).unwrap();
+        }
+        // the held funds aren't a debt yet
+        assert_eq!(account.info.debt, 0f32);
+        account
+            .process_transaction(&Transaction::new(TransactionType::Chargeback, 0, 1, None))
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn test_debt_follows_negative_balance() {
+        let mut account = account_in_debt(false);
+        assert_eq!(balance(&account), (-8f32, 0f32, -8f32, true));
+        assert_eq!(account.info.debt, 8f32);
+        assert!(account.info.in_debt());
+
+        // the deposit brings the balances back up
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 3, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        assert_eq!(balance(&account), (-3f32, 0f32, -3f32, true));
+        assert_eq!(account.info.debt, 3f32);
+    }
+
+    #[test]
+    fn test_debt_is_repaid_first() {
+        let mut account = account_in_debt(true);
+        assert_eq!(balance(&account), (0f32, 0f32, 0f32, true));
+        assert_eq!(account.info.debt, 8f32);
+
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 3, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        assert_eq!(balance(&account), (0f32, 0f32, 0f32, true));
+        assert_eq!(account.info.debt, 3f32);
+        let deposit = Transaction::new(TransactionType::Deposit, 0, 4, Some(5f32));
+        account.process_transaction(&deposit).unwrap();
+        assert_eq!(balance(&account), (2f32, 0f32, 2f32, true));
+        assert!(!account.info.in_debt());
+    }
 }
@@ -0,0 +1,261 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    client::precision_serialize, format_amount, transaction::prune_window, Amount, ClientID, Error,
+    Timestamp, Transaction, TransactionID, TransactionType,
+};
+
+/// the thresholds of the compliance report, nothing is reported without `threshold`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ComplianceConfig {
+    // every deposit or withdrawal above it is reported
+    pub threshold: Option<Amount>,
+    // how far below the threshold a transaction is "just below", as a fraction of it
+    pub structuring_margin: f32,
+    // how many just-below transactions of a client make a structuring pattern
+    pub structuring_count: usize,
+    // the period they must fall in, in the unit of the timestamp column
+    // every one of them counts without it
+    pub structuring_window: Option<Timestamp>,
+}
+
+impl Default for ComplianceConfig {
+    fn default() -> Self {
+        Self {
+            threshold: None,
+            structuring_margin: 0.1,
+            structuring_count: 3,
+            structuring_window: None,
+        }
+    }
+}
+
+impl ComplianceConfig {
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| {
+            Err(Error::InvalidConfig {
+                reason: format!("compliance: {}", reason),
+            })
+        };
+        if matches!(self.threshold, Some(threshold) if threshold <= 0f32 || threshold.is_nan()) {
+            return invalid("the threshold must be a positive amount");
+        }
+        if !(self.structuring_margin > 0f32 && self.structuring_margin < 1f32) {
+            return invalid("structuring_margin must be in (0, 1)");
+        }
+        if self.structuring_count < 2 {
+            return invalid("structuring_count must be at least 2");
+        }
+        Ok(())
+    }
+}
+
+/// why a transaction or client is in the compliance report
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ComplianceKind {
+    // a deposit or withdrawal above the threshold
+    LargeTransaction,
+    // several transactions of the client just below the threshold
+    Structuring,
+}
+
+/// a line of the compliance report
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ComplianceFlag {
+    pub kind: ComplianceKind,
+    pub client: ClientID,
+    // for a structuring pattern, the transaction that completed it
+    pub tx: TransactionID,
+    // for a structuring pattern, the sum of the transactions in it
+    #[serde(serialize_with = "precision_serialize")]
+    pub amount: Amount,
+    pub count: usize,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// a line of the compliance report, the amount already formatted
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ComplianceRecord {
+    pub kind: ComplianceKind,
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub amount: String,
+    pub count: usize,
+    pub timestamp: Option<Timestamp>,
+}
+
+impl ComplianceFlag {
+    /// the line of the report, the amount with `precision` decimal places
+    pub fn record(&self, precision: usize) -> ComplianceRecord {
+        ComplianceRecord {
+            kind: self.kind,
+            client: self.client,
+            tx: self.tx,
+            amount: format_amount(self.amount, precision),
+            count: self.count,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// watch the processed transactions and flag what the regulators want to see
+/// it never blocks a transaction, the flags only go into the report
+pub struct ComplianceMonitor {
+    config: ComplianceConfig,
+    // the recent just-below transactions of each client
+    near_threshold: HashMap<ClientID, VecDeque<(Option<Timestamp>, Amount)>>,
+    flags: Vec<ComplianceFlag>,
+}
+
+impl ComplianceMonitor {
+    pub fn new(config: ComplianceConfig) -> Self {
+        Self {
+            config,
+            near_threshold: HashMap::new(),
+            flags: Vec::new(),
+        }
+    }
+
+    /// look at a transaction that was applied
+    pub fn observe(&mut self, transaction: &Transaction) {
+        let (threshold, amount) = match (
+            self.config.threshold,
+            transaction.t_type(),
+            transaction.amount(),
+        ) {
+            (
+                Some(threshold),
+                TransactionType::Deposit | TransactionType::Withdrawal,
+                Some(amount),
+            ) => (threshold, amount),
+            _ => return,
+        };
+        if amount > threshold {
+            self.flags.push(ComplianceFlag {
+                kind: ComplianceKind::LargeTransaction,
+                client: transaction.client_id(),
+                tx: transaction.id(),
+                amount,
+                count: 1,
+                timestamp: transaction.timestamp(),
+            });
+        } else if amount >= threshold * (1f32 - self.config.structuring_margin) {
+            self.observe_near_threshold(transaction, amount);
+        }
+    }
+
+    fn observe_near_threshold(&mut self, transaction: &Transaction, amount: Amount) {
+        let now = transaction.timestamp();
+        let recent = self
+            .near_threshold
+            .entry(transaction.client_id())
+            .or_default();
+        if let Some(window) = self.config.structuring_window {
+            prune_window(recent, window, now);
+        }
+        recent.push_back((now, amount));
+        if recent.len() >= self.config.structuring_count {
+            // the pattern is reported once, the next one starts from scratch
+            self.flags.push(ComplianceFlag {
+                kind: ComplianceKind::Structuring,
+                client: transaction.client_id(),
+                tx: transaction.id(),
+                amount: recent.iter().map(|(_, amount)| amount).sum(),
+                count: recent.len(),
+                timestamp: now,
+            });
+            recent.clear();
+        }
+    }
+
+    /// the flags in the order they were raised
+    pub fn flags(&self) -> &[ComplianceFlag] {
+        &self.flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ComplianceConfig, ComplianceKind, ComplianceMonitor, Transaction, TransactionType,
+    };
+
+    fn deposit(client: u16, tx: u32, amount: f32, timestamp: u64) -> Transaction {
+        Transaction::new(TransactionType::Deposit, client, tx, Some(amount))
+            .with_timestamp(timestamp)
+    }
+
+    fn monitor(window: Option<u64>) -> ComplianceMonitor {
+        ComplianceMonitor::new(ComplianceConfig {
+            threshold: Some(10000.0),
+            structuring_window: window,
+            ..ComplianceConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_large_transactions() {
+        let mut monitor = monitor(None);
+        monitor.observe(&deposit(1, 1, 10000.0, 0));
+        monitor.observe(&deposit(1, 2, 10000.5, 0));
+        monitor.observe(&Transaction::new(
+            TransactionType::Withdrawal,
+            2,
+            3,
+            Some(20000.0),
+        ));
+        let flagged: Vec<u32> = monitor.flags().iter().map(|flag| flag.tx).collect();
+        assert_eq!(flagged, vec![2, 3]);
+        assert!(monitor
+            .flags()
+            .iter()
+            .all(|flag| flag.kind == ComplianceKind::LargeTransaction));
+        // nothing without a threshold
+        let mut monitor = ComplianceMonitor::new(ComplianceConfig::default());
+        monitor.observe(&deposit(1, 1, 1e9, 0));
+        assert!(monitor.flags().is_empty());
+    }
+
+    #[test]
+    fn test_structuring() {
+        let mut monitor = monitor(Some(100));
+        // 8999 isn't just below, client 2 is on its own
+        for (client, tx, amount, timestamp) in [
+            (1, 1, 9500.0, 0),
+            (1, 2, 8999.0, 10),
+            (2, 3, 9900.0, 20),
+            (1, 4, 9900.0, 50),
+        ] {
+            monitor.observe(&deposit(client, tx, amount, timestamp));
+        }
+        assert!(monitor.flags().is_empty());
+        // the first one is out of the window by now
+        monitor.observe(&deposit(1, 5, 9000.0, 100));
+        assert!(monitor.flags().is_empty());
+        monitor.observe(&deposit(1, 6, 9000.0, 120));
+        assert_eq!(monitor.flags().len(), 1);
+        let flag = &monitor.flags()[0];
+        assert_eq!(flag.kind, ComplianceKind::Structuring);
+        assert_eq!((flag.client, flag.tx, flag.count), (1, 6, 3));
+        assert_eq!(flag.amount, 27900.0);
+    }
+
+    #[test]
+    fn test_structuring_without_timestamps() {
+        // the window can't tell how old they are, so every one counts
+        let mut monitor = monitor(Some(100));
+        for tx in 1..=3 {
+            monitor.observe(&Transaction::new(
+                TransactionType::Deposit,
+                1,
+                tx,
+                Some(9500.0),
+            ));
+        }
+        assert_eq!(monitor.flags().len(), 1);
+        assert_eq!(monitor.flags()[0].count, 3);
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    path::Path,
+};
+
+use crate::Error;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// the compression of an input stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// detect the compression from the first bytes of the stream
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// detect the compression from the file extension, e.g. `.csv.gz`
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// wrap the stream in a streaming decoder if it is compressed
+/// the magic bytes win, and `hint`(usually from the file extension) is only used
+/// when the stream is too short to tell
+pub fn decompress_reader<'a, R: Read + 'a>(
+    mut stream_reader: R,
+    hint: Compression,
+) -> std::io::Result<Box<dyn Read + 'a>> {
+    // a single read could return fewer bytes, keep reading up to the longest magic number
+    let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+    (&mut stream_reader)
+        .take(ZSTD_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let compression = match Compression::from_magic(&head) {
+        Some(compression) => compression,
+        None if head.len() < ZSTD_MAGIC.len() => hint,
+        None => Compression::None,
+    };
+    // put the head back in front of the rest of the stream
+    let buf_reader = BufReader::new(Cursor::new(head).chain(stream_reader));
+    Ok(match compression {
+        Compression::None => Box::new(buf_reader),
+        // the dumps may be several gzip members concatenated together
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(buf_reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(buf_reader)?),
+    })
+}
+
+/// open the file and decompress it on the fly when needed
+pub fn open_input(path: &Path) -> Result<Box<dyn Read>, Error> {
+    File::open(path)
+        .and_then(|f| decompress_reader(f, Compression::from_path(path)))
+        .map_err(|err| Error::OpenInput {
+            path: path.display().to_string(),
+            reason: err.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decompress_reader, open_input, Compression, InputFormat, Transaction};
+    use std::{io::Read, path::Path};
+
+    // a stream that returns a single byte per read, like a slow pipe
+    struct OneByteReader<'a>(&'a [u8]);
+
+    impl Read for OneByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((byte, rest)), Some(first)) => {
+                    *first = *byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    const SAMPLES: [&str; 6] = [
+        "all_transactions",
+        "cant_withdrawal",
+        "invalid_deposit",
+        "locked_account",
+        "precision_check",
+        "wrong_dispute",
+    ];
+
+    fn read_to_string(path: &str) -> String {
+        let mut data = String::new();
+        open_input(Path::new(path))
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_compressed_copies_match_the_samples() {
+        for sample in SAMPLES {
+            let raw = read_to_string(&format!("./sample_csv/{}.csv", sample));
+            assert_eq!(
+                raw,
+                read_to_string(&format!("./sample_csv/{}.csv.gz", sample))
+            );
+            assert_eq!(
+                raw,
+                read_to_string(&format!("./sample_csv/{}.csv.zst", sample))
+            );
+        }
+    }
+
+    #[test]
+    fn test_compressed_stream_feeds_the_csv_reader() {
+        for extension in ["gz", "zst"] {
+            let path = format!("./sample_csv/all_transactions.csv.{}", extension);
+            let transactions: Vec<Transaction> = InputFormat::Csv
+                .reader(open_input(Path::new(&path)).unwrap())
+                .map(|t| t.unwrap())
+                .collect();
+            assert_eq!(transactions.len(), 9);
+        }
+    }
+
+    #[test]
+    fn test_magic_bytes_win_over_extension() {
+        // a gzip file without the extension is still decompressed
+        let data = std::fs::read("./sample_csv/precision_check.csv.gz").unwrap();
+        let mut decoded = String::new();
+        decompress_reader(&data[..], Compression::None)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert!(decoded.starts_with("type, client, tx, amount"));
+        assert_eq!(
+            Compression::from_path(Path::new("a.csv.zst")),
+            Compression::Zstd
+        );
+    }
+
+    #[test]
+    fn test_magic_bytes_over_short_reads() {
+        let raw = std::fs::read_to_string("./sample_csv/all_transactions.csv").unwrap();
+        for extension in ["gz", "zst"] {
+            let data =
+                std::fs::read(format!("./sample_csv/all_transactions.csv.{}", extension)).unwrap();
+            let mut decoded = String::new();
+            decompress_reader(OneByteReader(&data), Compression::None)
+                .unwrap()
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, raw);
+        }
+        // a plain stream keeps its first bytes
+        let mut plain = String::new();
+        decompress_reader(OneByteReader(raw.as_bytes()), Compression::None)
+            .unwrap()
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, raw);
+    }
+}
@@ -0,0 +1,383 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    AccountStatus, Amount, ClientID, ComplianceConfig, Error, RiskRuleConfig, Timestamp,
+    TransactionType, DEFAULT_PRECISION,
+};
+
+// f32 can't hold more decimal places than this anyway
+const MAX_PRECISION: usize = 8;
+
+/// how a dispute of a withdrawal moves the funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WithdrawalDisputeMode {
+    // a withdrawal can't be disputed at all
+    Forbid,
+    // the dispute reverses the withdrawal: available increases and held decreases(goes negative)
+    // a chargeback gives the money back to the client
+    #[default]
+    Reversal,
+    // the disputed amount is credited as held funds: held and total increase
+    // a resolve takes the credit away, a chargeback releases it to available
+    HeldCredit,
+}
+
+/// which transactions are still allowed on an account that isn't active
+/// the administrative operations aren't part of it, they have their own rules
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusPolicy {
+    pub frozen: Vec<TransactionType>,
+    pub locked: Vec<TransactionType>,
+    pub closed: Vec<TransactionType>,
+}
+
+impl Default for StatusPolicy {
+    // a frozen account only refuses withdrawals, locked and closed accounts refuse everything
+    fn default() -> Self {
+        Self {
+            frozen: vec![
+                TransactionType::Deposit,
+                TransactionType::Dispute,
+                TransactionType::Resolve,
+                TransactionType::Chargeback,
+            ],
+            locked: Vec::new(),
+            closed: Vec::new(),
+        }
+    }
+}
+
+impl StatusPolicy {
+    pub fn allows(&self, status: AccountStatus, t_type: TransactionType) -> bool {
+        match status {
+            AccountStatus::Active => true,
+            AccountStatus::Frozen => self.frozen.contains(&t_type),
+            AccountStatus::Locked => self.locked.contains(&t_type),
+            AccountStatus::Closed => self.closed.contains(&t_type),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for (status, allowed) in [
+            ("frozen", &self.frozen),
+            ("locked", &self.locked),
+            ("closed", &self.closed),
+        ] {
+            if let Some(t_type) = allowed.iter().find(|t_type| t_type.is_admin()) {
+                return Err(Error::InvalidConfig {
+                    reason: format!(
+                        "status_policy.{} can't list the administrative operation {:?}",
+                        status, t_type
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// what happens to a dispute that is still open at its deadline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeExpiryAction {
+    // the client keeps the funds
+    #[default]
+    Resolve,
+    // the funds are reversed and the account is locked, like a chargeback row
+    Chargeback,
+}
+
+/// the time limits of the disputes, in the unit of the timestamp column
+/// they only apply to the transactions with a timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeWindow {
+    // how long after a transaction it could still be disputed
+    pub max_age: Option<Timestamp>,
+    // how long a dispute could stay open before `on_expiry` settles it
+    pub max_open: Option<Timestamp>,
+    pub on_expiry: DisputeExpiryAction,
+}
+
+/// how many times the transactions could be disputed
+/// a resolved transaction could otherwise be disputed again and again
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeLimits {
+    // the disputes of one transaction, over its whole life
+    // every dispute row counts, a partial one as well
+    pub max_per_transaction: Option<u32>,
+    // the disputes of one client within `period`
+    pub max_per_client: Option<u32>,
+    // in the unit of the timestamp column, every dispute counts without it
+    pub period: Option<Timestamp>,
+}
+
+/// what happens to a dispute that would take the available funds below the overdraft limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeBelowLimit {
+    // the funds are held anyway
+    #[default]
+    Allow,
+    // the dispute is rejected
+    Reject,
+    // the funds are held and the account is frozen, so nothing more goes out
+    Freeze,
+}
+
+/// how far below zero the available funds of the clients could go
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OverdraftPolicy {
+    // the limit of every client without its own
+    pub limit: Amount,
+    // the limits of some clients, keyed by client id(toml keys are strings)
+    pub clients: BTreeMap<String, Amount>,
+    pub on_dispute_below_limit: DisputeBelowLimit,
+}
+
+impl OverdraftPolicy {
+    /// the limit the account of the client starts with
+    pub fn limit_of(&self, client: ClientID) -> Amount {
+        self.clients
+            .get(&client.to_string())
+            .copied()
+            .unwrap_or(self.limit)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if !(self.limit >= 0f32 && self.limit.is_finite()) {
+            return Err(Error::InvalidConfig {
+                reason: format!(
+                    "overdraft.limit {} must be a non-negative finite amount",
+                    self.limit
+                ),
+            });
+        }
+        for (client, limit) in &self.clients {
+            if client.parse::<ClientID>().is_err() {
+                return Err(Error::InvalidConfig {
+                    reason: format!("overdraft.clients: {} is not a client id", client),
+                });
+            }
+            if !(*limit >= 0f32 && limit.is_finite()) {
+                return Err(Error::InvalidConfig {
+                    reason: format!(
+                        "overdraft.clients: the limit {} of client {} must be a non-negative finite amount",
+                        limit, client
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// how the debt of an account is handled
+/// the debt is how far the total funds are below the overdraft limit,
+/// e.g. after the chargeback of a deposit that was already withdrawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebtPolicy {
+    // take the debt out of the balances and pay it back from the next deposits first
+    // otherwise the balances stay negative and the debt only follows them
+    pub repay_first: bool,
+}
+
+/// the policies of the engine, loaded from a toml file at startup
+/// every key is optional, the default keeps the behavior described in the README
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    // what a dispute of a withdrawal does
+    pub withdrawal_dispute: WithdrawalDisputeMode,
+    // a disputed withdrawal could be charged back, which means the client gets the money
+    pub allow_withdrawal_chargeback: bool,
+    // a withdrawal needs both the total and the available funds to cover the amount
+    // otherwise only the available funds are checked
+    pub withdrawal_requires_total: bool,
+    // the number of decimal places of the amounts in the output
+    pub precision: usize,
+    // the transactions allowed on frozen, locked and closed accounts
+    pub status_policy: StatusPolicy,
+    // the time limits of the disputes, no limit by default
+    pub dispute_window: DisputeWindow,
+    // how many times the transactions could be disputed, no limit by default
+    pub dispute_limits: DisputeLimits,
+    // the overdraft limits of the clients, none by default
+    pub overdraft: OverdraftPolicy,
+    // how the debt of an account is handled
+    pub debt: DebtPolicy,
+    // the risk rules checked before every transaction, in order
+    pub risk_rules: Vec<RiskRuleConfig>,
+    // the thresholds of the compliance report, it never blocks a transaction
+    pub compliance: ComplianceConfig,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            withdrawal_dispute: WithdrawalDisputeMode::default(),
+            allow_withdrawal_chargeback: true,
+            withdrawal_requires_total: true,
+            precision: DEFAULT_PRECISION,
+            status_policy: StatusPolicy::default(),
+            dispute_window: DisputeWindow::default(),
+            dispute_limits: DisputeLimits::default(),
+            overdraft: OverdraftPolicy::default(),
+            debt: DebtPolicy::default(),
+            risk_rules: Vec::new(),
+            compliance: ComplianceConfig::default(),
+        }
+    }
+}
+
+impl EngineConfig {
+    /// parse and validate the config
+    pub fn from_toml(data: &str) -> Result<Self, Error> {
+        let config: Self = toml::from_str(data).map_err(|err| Error::InvalidConfig {
+            reason: err.to_string(),
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let data = std::fs::read_to_string(path).map_err(|err| Error::InvalidConfig {
+            reason: format!("can't read {}: {}", path.display(), err),
+        })?;
+        Self::from_toml(&data)
+    }
+
+    /// check the values that are well-formed but don't make sense
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.precision > MAX_PRECISION {
+            return Err(Error::InvalidConfig {
+                reason: format!(
+                    "precision {} is larger than the maximum {}",
+                    self.precision, MAX_PRECISION
+                ),
+            });
+        }
+        self.status_policy.validate()?;
+        self.overdraft.validate()?;
+        self.compliance.validate()?;
+        for (index, rule) in self.risk_rules.iter().enumerate() {
+            rule.validate()?;
+            if self.risk_rules[..index]
+                .iter()
+                .any(|other| other.id() == rule.id())
+            {
+                return Err(Error::InvalidConfig {
+                    reason: format!("the risk rule id {} is used twice", rule.id()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AccountStatus, DisputeExpiryAction, EngineConfig, Error, TransactionType,
+        WithdrawalDisputeMode,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn test_sample_config_is_the_default() {
+        let config = EngineConfig::from_file(Path::new("./sample_config/engine.toml")).unwrap();
+        assert_eq!(config, EngineConfig::default());
+        assert_eq!(
+            EngineConfig::from_toml("").unwrap(),
+            EngineConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_partial_config() {
+        let config = EngineConfig::from_toml("withdrawal_requires_total = false").unwrap();
+        assert!(!config.withdrawal_requires_total);
+        assert_eq!(config.withdrawal_dispute, WithdrawalDisputeMode::Reversal);
+        assert_eq!(config.precision, 4);
+
+        let config = EngineConfig::from_toml("withdrawal_dispute = \"held_credit\"").unwrap();
+        assert_eq!(config.withdrawal_dispute, WithdrawalDisputeMode::HeldCredit);
+
+        let config = EngineConfig::from_toml("[dispute_window]\nmax_open = 100").unwrap();
+        assert_eq!(config.dispute_window.max_age, None);
+        assert_eq!(config.dispute_window.max_open, Some(100));
+        assert_eq!(
+            config.dispute_window.on_expiry,
+            DisputeExpiryAction::Resolve
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for data in [
+            "precision = 9",
+            "precision = -1",
+            "withdrawal_dispute = \"sometimes\"",
+            "withdrawal_requires_total = \"yes\"",
+            "unknown_policy = true",
+            "[status_policy]\nlocked = [\"unlock\"]",
+            "[status_policy]\nlocked = [\"teleport\"]",
+            "[dispute_window]\non_expiry = \"ignore\"",
+            "[dispute_limits]\nmax_per_client = -1",
+            "[overdraft]\nlimit = -5.0",
+            "[overdraft.clients]\nalice = 5.0",
+            "[[risk_rules]]\nrule = \"max_withdrawal\"\nid = \"big\"\nlimit = -1.0",
+            "[[risk_rules]]\nrule = \"withdrawal_velocity\"\nid = \"fast\"\nmax_total = 1.0",
+            "[[risk_rules]]\nrule = \"dispute_ratio\"\nid = \"ratio\"\nmax_ratio = 2.0",
+            "[[risk_rules]]\nrule = \"guess\"\nid = \"what\"",
+            "[compliance]\nthreshold = 0.0",
+            "[compliance]\nstructuring_margin = 1.5",
+            "[compliance]\nstructuring_count = 1",
+            "[[risk_rules]]\nrule = \"max_withdrawal\"\nid = \"big\"\nlimit = 1.0\n[[risk_rules]]\nrule = \"max_withdrawal\"\nid = \"big\"\nlimit = 2.0",
+        ] {
+            assert!(
+                matches!(
+                    EngineConfig::from_toml(data),
+                    Err(Error::InvalidConfig { .. })
+                ),
+                "{}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_policy() {
+        let config = EngineConfig::from_toml(
+            "[status_policy]\nlocked = [\"deposit\", \"resolve\", \"chargeback\"]",
+        )
+        .unwrap();
+        let policy = &config.status_policy;
+        assert!(policy.allows(AccountStatus::Locked, TransactionType::Deposit));
+        assert!(policy.allows(AccountStatus::Locked, TransactionType::Chargeback));
+        assert!(!policy.allows(AccountStatus::Locked, TransactionType::Withdrawal));
+        assert!(!policy.allows(AccountStatus::Locked, TransactionType::Dispute));
+        // the other status keep their default
+        assert!(policy.allows(AccountStatus::Frozen, TransactionType::Deposit));
+        assert!(!policy.allows(AccountStatus::Frozen, TransactionType::Withdrawal));
+        assert!(!policy.allows(AccountStatus::Closed, TransactionType::Deposit));
+        assert!(policy.allows(AccountStatus::Active, TransactionType::Withdrawal));
+    }
+
+    #[test]
+    fn test_overdraft_limits() {
+        let config = EngineConfig::from_toml(
+            "[overdraft]\nlimit = 10.0\non_dispute_below_limit = \"freeze\"\n[overdraft.clients]\n5 = 100.0\n",
+        )
+        .unwrap();
+        assert_eq!(config.overdraft.limit_of(5), 100.0);
+        assert_eq!(config.overdraft.limit_of(6), 10.0);
+        assert_eq!(EngineConfig::default().overdraft.limit_of(5), 0.0);
+    }
+}
@@ -0,0 +1,268 @@
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{format_amount, Amount, ClientAccountInfo, ClientID, Error};
+
+/// one difference between two sets of balances
+#[derive(Debug, Clone)]
+pub enum AccountDiff {
+    // the client only shows up on the right side
+    MissingLeft(ClientID),
+    // the client only shows up on the left side
+    MissingRight(ClientID),
+    Mismatch {
+        left: ClientAccountInfo,
+        right: ClientAccountInfo,
+        // the fields that don't match, in the order of the columns
+        fields: Vec<DiffField>,
+    },
+}
+
+/// why a client is in the machine-readable diff
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffKind {
+    MissingLeft,
+    MissingRight,
+    Mismatch,
+}
+
+/// a column of the balances that is compared
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffField {
+    Available,
+    Held,
+    Total,
+    Debt,
+    Status,
+}
+
+impl fmt::Display for DiffField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = match self {
+            DiffField::Available => "available",
+            DiffField::Held => "held",
+            DiffField::Total => "total",
+            DiffField::Debt => "debt",
+            DiffField::Status => "status",
+        };
+        write!(f, "{}", field)
+    }
+}
+
+/// a line of the machine-readable diff, one per missing client or mismatched field
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiffRecord {
+    pub client: ClientID,
+    pub kind: DiffKind,
+    pub field: Option<DiffField>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl AccountDiff {
+    /// the lines of the machine-readable diff, the amounts with `precision` decimal places
+    pub fn records(&self, precision: usize) -> Vec<DiffRecord> {
+        let missing = |client, kind| DiffRecord {
+            client,
+            kind,
+            field: None,
+            left: None,
+            right: None,
+        };
+        match self {
+            AccountDiff::MissingLeft(client) => vec![missing(*client, DiffKind::MissingLeft)],
+            AccountDiff::MissingRight(client) => vec![missing(*client, DiffKind::MissingRight)],
+            AccountDiff::Mismatch {
+                left,
+                right,
+                fields,
+            } => fields
+                .iter()
+                .map(|field| DiffRecord {
+                    client: left.client,
+                    kind: DiffKind::Mismatch,
+                    field: Some(*field),
+                    left: Some(field_value(left, *field, precision)),
+                    right: Some(field_value(right, *field, precision)),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn field_value(info: &ClientAccountInfo, field: DiffField, precision: usize) -> String {
+    match field {
+        DiffField::Available => format_amount(info.available, precision),
+        DiffField::Held => format_amount(info.held, precision),
+        DiffField::Total => format_amount(info.total, precision),
+        DiffField::Debt => format_amount(info.debt, precision),
+        DiffField::Status => info.status.to_string(),
+    }
+}
+
+/// how the diff is printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffFormat {
+    // a line per client for the humans
+    #[default]
+    Text,
+    // a row per missing client or mismatched field
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for DiffFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(DiffFormat::Text),
+            "csv" => Ok(DiffFormat::Csv),
+            "jsonl" | "ndjson" => Ok(DiffFormat::Jsonl),
+            _ => Err(Error::UnknownFormat {
+                format: s.to_string(),
+            }),
+        }
+    }
+}
+
+// the fields that don't match, the amounts match within `tolerance`
+// or as they are printed without it
+fn mismatched_fields(
+    left: &ClientAccountInfo,
+    right: &ClientAccountInfo,
+    tolerance: Option<Amount>,
+    precision: usize,
+) -> Vec<DiffField> {
+    let same_amount = |a: Amount, b: Amount| match tolerance {
+        Some(tolerance) => (a - b).abs() <= tolerance,
+        None => format_amount(a, precision) == format_amount(b, precision),
+    };
+    let mut fields: Vec<DiffField> = [
+        (DiffField::Available, left.available, right.available),
+        (DiffField::Held, left.held, right.held),
+        (DiffField::Total, left.total, right.total),
+        (DiffField::Debt, left.debt, right.debt),
+    ]
+    .into_iter()
+    .filter(|(_, a, b)| !same_amount(*a, *b))
+    .map(|(field, _, _)| field)
+    .collect();
+    if left.status != right.status {
+        fields.push(DiffField::Status);
+    }
+    fields
+}
+
+/// compare two sets of balances client by client, the diffs are ordered by client id
+/// the amounts could differ by `tolerance`, they are compared as they are printed with
+/// `precision` decimal places without it
+pub fn diff_accounts(
+    left: &[ClientAccountInfo],
+    right: &[ClientAccountInfo],
+    tolerance: Option<Amount>,
+    precision: usize,
+) -> Vec<AccountDiff> {
+    let left: BTreeMap<ClientID, &ClientAccountInfo> =
+        left.iter().map(|info| (info.client, info)).collect();
+    let right: BTreeMap<ClientID, &ClientAccountInfo> =
+        right.iter().map(|info| (info.client, info)).collect();
+    let mut clients: Vec<ClientID> = left.keys().chain(right.keys()).copied().collect();
+    clients.sort_unstable();
+    clients.dedup();
+
+    clients
+        .into_iter()
+        .filter_map(|client| match (left.get(&client), right.get(&client)) {
+            (Some(l), Some(r)) => {
+                let fields = mismatched_fields(l, r, tolerance, precision);
+                (!fields.is_empty()).then(|| AccountDiff::Mismatch {
+                    left: (*l).clone(),
+                    right: (*r).clone(),
+                    fields,
+                })
+            }
+            (Some(_), None) => Some(AccountDiff::MissingRight(client)),
+            (None, _) => Some(AccountDiff::MissingLeft(client)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        diff_accounts, AccountDiff, AccountStatus, ClientAccountInfo, DiffField, DiffKind,
+        DiffRecord,
+    };
+
+    #[test]
+    fn test_diff_accounts() {
+        let mut changed = ClientAccountInfo::new(2);
+        changed.available = 1.0;
+        changed.total = 1.0;
+        let mut rounded = ClientAccountInfo::new(3);
+        rounded.available = 0.00001;
+        let left = vec![
+            ClientAccountInfo::new(1),
+            ClientAccountInfo::new(2),
+            ClientAccountInfo::new(3),
+        ];
+        let right = vec![changed, rounded, ClientAccountInfo::new(4)];
+
+        let diffs = diff_accounts(&left, &right, None, 4);
+        assert_eq!(diffs.len(), 3);
+        assert!(matches!(diffs[0], AccountDiff::MissingRight(1)));
+        assert!(matches!(&diffs[1], AccountDiff::Mismatch { right, .. } if right.client == 2));
+        assert!(matches!(diffs[2], AccountDiff::MissingLeft(4)));
+        assert!(diff_accounts(&left, &left, None, 4).is_empty());
+    }
+
+    #[test]
+    fn test_diff_with_tolerance() {
+        let mut left = ClientAccountInfo::new(1);
+        left.available = 1.0;
+        left.total = 1.0;
+        let mut right = left.clone();
+        right.available = 1.009;
+        right.total = 1.02;
+        right.status = AccountStatus::Locked;
+
+        assert!(diff_accounts(&[left.clone()], &[right.clone()], Some(0.05), 4)
+            .iter()
+            .all(
+                |diff| matches!(diff, AccountDiff::Mismatch { fields, .. } if fields == &[DiffField::Status])
+            ));
+        let diffs = diff_accounts(&[left], &[right], Some(0.01), 4);
+        assert_eq!(
+            diffs[0].records(2),
+            vec![
+                DiffRecord {
+                    client: 1,
+                    kind: DiffKind::Mismatch,
+                    field: Some(DiffField::Total),
+                    left: Some("1.00".to_string()),
+                    right: Some("1.02".to_string()),
+                },
+                DiffRecord {
+                    client: 1,
+                    kind: DiffKind::Mismatch,
+                    field: Some(DiffField::Status),
+                    left: Some("active".to_string()),
+                    right: Some("locked".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            AccountDiff::MissingLeft(2).records(2)[0].kind,
+            DiffKind::MissingLeft
+        );
+        // the names of the machine-readable diff
+        let record = serde_json::to_string(&diffs[0].records(2)[0]).unwrap();
+        assert_eq!(
+            record,
+            r#"{"client":1,"kind":"mismatch","field":"total","left":"1.00","right":"1.02"}"#
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    client::precision_serialize, format_amount, Amount, ClientID, Timestamp, TransactionID,
+    TransactionType,
+};
+
+// the bucket of the chargebacks whose dispute had no reason code
+const NO_REASON_CODE: &str = "unspecified";
+
+/// an open dispute as it shows up in the statement of the disputes
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct DisputeInfo {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    // the held amount, always positive
+    #[serde(serialize_with = "precision_serialize")]
+    pub amount: Amount,
+    pub opened_at: Option<Timestamp>,
+    pub reason_code: Option<String>,
+    pub note: Option<String>,
+}
+
+/// a line of the statement of the disputes, the amount already formatted
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct DisputeRecord {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub amount: String,
+    pub opened_at: Option<Timestamp>,
+    pub reason_code: Option<String>,
+    pub note: Option<String>,
+}
+
+impl DisputeInfo {
+    /// the line of the statement, the amount with `precision` decimal places
+    pub fn record(&self, precision: usize) -> DisputeRecord {
+        DisputeRecord {
+            client: self.client,
+            tx: self.tx,
+            amount: format_amount(self.amount, precision),
+            opened_at: self.opened_at,
+            reason_code: self.reason_code.clone(),
+            note: self.note.clone(),
+        }
+    }
+}
+
+/// a dispute, resolve or chargeback of a transaction, kept in its dispute history
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct DisputeEvent {
+    pub t_type: TransactionType,
+    // the covered amount, always positive
+    pub amount: Amount,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// a chargeback, with the reason code of the dispute it settled
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChargebackRecord {
+    pub client: ClientID,
+    pub tx: TransactionID,
+    // the charged back amount, always positive
+    #[serde(serialize_with = "precision_serialize")]
+    pub amount: Amount,
+    pub reason_code: Option<String>,
+}
+
+/// the chargeback losses of one reason code
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ChargebackLoss {
+    pub reason_code: String,
+    pub count: usize,
+    #[serde(serialize_with = "precision_serialize")]
+    pub amount: Amount,
+}
+
+/// a line of the chargeback report, the amount already formatted
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChargebackLossRecord {
+    pub reason_code: String,
+    pub count: usize,
+    pub amount: String,
+}
+
+impl ChargebackLoss {
+    /// the line of the report, the amount with `precision` decimal places
+    pub fn record(&self, precision: usize) -> ChargebackLossRecord {
+        ChargebackLossRecord {
+            reason_code: self.reason_code.clone(),
+            count: self.count,
+            amount: format_amount(self.amount, precision),
+        }
+    }
+}
+
+/// aggregate the chargebacks per reason code
+#[derive(Debug, Default)]
+pub struct ChargebackReport {
+    losses: BTreeMap<String, (usize, Amount)>,
+}
+
+impl ChargebackReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, chargeback: &ChargebackRecord) {
+        let reason_code = chargeback.reason_code.as_deref().unwrap_or(NO_REASON_CODE);
+        let (count, amount) = self.losses.entry(reason_code.to_string()).or_default();
+        *count += 1;
+        *amount += chargeback.amount;
+    }
+
+    /// the losses ordered by reason code
+    pub fn losses(&self) -> Vec<ChargebackLoss> {
+        self.losses
+            .iter()
+            .map(|(reason_code, (count, amount))| ChargebackLoss {
+                reason_code: reason_code.clone(),
+                count: *count,
+                amount: *amount,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChargebackRecord, ChargebackReport};
+
+    fn chargeback(tx: u32, amount: f32, reason_code: Option<&str>) -> ChargebackRecord {
+        ChargebackRecord {
+            client: 1,
+            tx,
+            amount,
+            reason_code: reason_code.map(|code| code.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_losses_per_reason_code() {
+        let mut report = ChargebackReport::new();
+        report.record(&chargeback(1, 2.5, Some("fraud")));
+        report.record(&chargeback(2, 1.0, None));
+        report.record(&chargeback(3, 0.5, Some("fraud")));
+        let losses = report.losses();
+        assert_eq!(losses.len(), 2);
+        assert_eq!(
+            (
+                losses[0].reason_code.as_str(),
+                losses[0].count,
+                losses[0].amount
+            ),
+            ("fraud", 2, 3.0)
+        );
+        assert_eq!(
+            (
+                losses[1].reason_code.as_str(),
+                losses[1].count,
+                losses[1].amount
+            ),
+            ("unspecified", 1, 1.0)
+        );
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for loss in losses {
+            writer.serialize(loss).unwrap();
+        }
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "reason_code,count,amount\nfraud,2,3.0000\nunspecified,1,1.0000\n"
+        );
+    }
+}
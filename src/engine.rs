@@ -0,0 +1,857 @@
+use std::{
+    cell::RefCell,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
+use tracing::{debug, debug_span, error, info, trace, warn};
+
+use crate::{
+    audit::{check_account, check_ledger, net_funds, AccountSnapshot},
+    observer::Observed,
+    Amount, AuditEntry, ChargebackReport, ClientAccount, ClientAccountInfo, ClientID, DisputeInfo,
+    EngineConfig, Error, Event, MetricsRegistry, Observer, OutputOrder, RiskAction, RiskDecision,
+    RiskRule, Timestamp, Transaction, TransactionHashmapDB, TransactionID, TransactionType,
+};
+
+/// the engine owns every client account and the transaction database they share
+/// the accounts are created on the first transaction of a client
+pub struct Engine {
+    accounts: HashMap<ClientID, ClientAccount>,
+    // the clients in the order their accounts were created
+    insertion_order: Vec<ClientID>,
+    transaction_db: Rc<RefCell<TransactionHashmapDB>>,
+    config: Rc<EngineConfig>,
+    // the deadlines of the open disputes, the earliest on top
+    // an entry stays here after its dispute is settled and is skipped when it comes up
+    dispute_deadlines: BinaryHeap<Reverse<(Timestamp, TransactionID, ClientID)>>,
+    // the resolves and chargebacks made up for the expired disputes, until they are taken
+    expired_disputes: Vec<Transaction>,
+    // checked in order before every transaction
+    risk_rules: Vec<Box<dyn RiskRule>>,
+    // every time a risk rule fired
+    risk_decisions: Vec<RiskDecision>,
+    // check the invariants of the account after every transaction
+    audit: bool,
+    // in audit mode, the money the transactions brought in so far, see `check_ledger`
+    ledger: f64,
+    // an invariant broken while `expire_disputes` settled a dispute, for the next transaction
+    violation: Option<Error>,
+    // shared with whoever wants to read them, e.g. the metrics endpoint
+    metrics: Arc<MetricsRegistry>,
+    // called with every event, in the order they were added
+    observers: Vec<Box<dyn Observer>>,
+}
+
+fn notify(observers: &mut [Box<dyn Observer>], event: &Event) {
+    for observer in observers.iter_mut() {
+        observer.on_event(event);
+    }
+}
+
+// the open disputes and the held funds of an account, to follow them in the metrics
+fn dispute_gauges(account: &ClientAccount) -> (i64, Amount) {
+    (account.open_dispute_count() as i64, account.info.held)
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_config(EngineConfig::default())
+    }
+
+    pub fn with_config(config: EngineConfig) -> Self {
+        let risk_rules = config.risk_rules.iter().map(|rule| rule.build()).collect();
+        Self {
+            accounts: HashMap::new(),
+            insertion_order: Vec::new(),
+            transaction_db: Rc::new(RefCell::new(TransactionHashmapDB::new())),
+            config: Rc::new(config),
+            dispute_deadlines: BinaryHeap::new(),
+            expired_disputes: Vec::new(),
+            risk_rules,
+            risk_decisions: Vec::new(),
+            audit: false,
+            ledger: 0f64,
+            violation: None,
+            metrics: Arc::new(MetricsRegistry::new()),
+            observers: Vec::new(),
+        }
+    }
+
+    /// call the observer with every event from now on, see `Event`
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    /// the metrics of the engine, updated with every transaction
+    pub fn metrics(&self) -> Arc<MetricsRegistry> {
+        self.metrics.clone()
+    }
+
+    /// check the invariants of the account after every transaction, see `audit.rs`
+    /// the transaction that breaks one is reported with `Error::InvariantViolation`
+    /// it costs a copy of the balances for every transaction
+    pub fn enable_audit(&mut self) {
+        if !self.audit {
+            // the ledger starts from the money that is already there
+            self.ledger = self
+                .accounts
+                .values()
+                .map(|account| net_funds(&account.info, &self.config) as f64)
+                .sum();
+        }
+        self.audit = true;
+    }
+
+    /// in audit mode, check that the accounts hold what the deposits, withdrawals, chargebacks
+    /// and held credits brought in so far
+    /// it goes through every account, so unlike the checks of the accounts it doesn't run per
+    /// transaction, the caller checks it once at the end of a run, e.g. after the last input
+    pub fn check_ledger(&self) -> Result<(), Error> {
+        if !self.audit {
+            return Ok(());
+        }
+        check_ledger(
+            self.accounts.values().map(|account| &account.info),
+            self.ledger,
+            &self.config,
+        )
+    }
+
+    /// plug in a risk rule on top of the built-in ones from the config
+    pub fn add_risk_rule(&mut self, rule: Box<dyn RiskRule>) {
+        self.risk_rules.push(rule);
+    }
+
+    /// the decisions of the risk rules that fired so far
+    pub fn risk_decisions(&self) -> &[RiskDecision] {
+        &self.risk_decisions
+    }
+
+    // run every risk rule on the transaction and record what they decide
+    // returns the first rejection, or the rules that want the account frozen
+    // a rejection is recorded right away, a freeze once it is done, see `apply_to_account`
+    fn check_risk(&mut self, transaction: &Transaction) -> Result<Vec<RiskDecision>, Error> {
+        let info = &self.accounts[&transaction.client_id()].info;
+        let mut freezes = Vec::new();
+        for rule in self.risk_rules.iter_mut() {
+            if let Some((action, reason)) = rule.check(transaction, info) {
+                let decision = RiskDecision {
+                    rule: rule.id().to_string(),
+                    client: transaction.client_id(),
+                    tx: transaction.id(),
+                    action,
+                    reason,
+                };
+                match action {
+                    RiskAction::Reject => {
+                        warn!(rule = %decision.rule, action = ?action, reason = %decision.reason, "risk rule fired");
+                        self.risk_decisions.push(decision.clone());
+                        return Err(Error::RiskRejected {
+                            client: decision.client,
+                            tx: decision.tx,
+                            rule: decision.rule,
+                            reason: decision.reason,
+                        });
+                    }
+                    RiskAction::Freeze => freezes.push(decision),
+                }
+            }
+        }
+        Ok(freezes)
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    fn new_account(&self, client_id: ClientID) -> ClientAccount {
+        ClientAccount::new_with_config(client_id, self.transaction_db.clone(), self.config.clone())
+    }
+
+    /// route the transaction to the account of its client
+    /// the disputes whose deadline passed before the timestamp of the transaction are settled first
+    /// then the risk rules could reject the transaction, or freeze the account once it is applied
+    /// the observers hear about the outcome at the end
+    pub fn process_transaction(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        let _span = debug_span!(
+            "transaction",
+            client = transaction.client_id(),
+            tx = transaction.id(),
+            t_type = ?transaction.t_type()
+        )
+        .entered();
+        let started = Instant::now();
+        let result = self.apply(transaction);
+        match &result {
+            Ok(()) => trace!("applied"),
+            Err(e @ Error::InvariantViolation { .. }) => error!(error = %e, "invariant violated"),
+            Err(e) => debug!(error = %e, "rejected"),
+        }
+        self.metrics
+            .record(transaction.t_type(), &result, started.elapsed());
+        result
+    }
+
+    fn apply(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        if let Some(violation) = self.violation.take() {
+            return Err(violation);
+        }
+        if let Some(now) = transaction.timestamp() {
+            self.settle_expired(now).1?;
+        }
+        let client_id = transaction.client_id();
+        if !self.accounts.contains_key(&client_id) {
+            self.accounts.insert(client_id, self.new_account(client_id));
+            self.insertion_order.push(client_id);
+        }
+        let before =
+            (!self.observers.is_empty()).then(|| Observed::take(&self.accounts[&client_id]));
+        let result = self.apply_to_account(transaction, client_id);
+        if let Some(before) = before {
+            let account = &self.accounts[&client_id];
+            match &result {
+                Ok(()) => {
+                    notify(
+                        &mut self.observers,
+                        &Event::Applied {
+                            transaction,
+                            before: &before.info,
+                            after: &account.info,
+                        },
+                    );
+                    if transaction.t_type() == TransactionType::Dispute
+                        && before.dispute_opened(account)
+                    {
+                        notify(
+                            &mut self.observers,
+                            &Event::DisputeOpened {
+                                transaction,
+                                before: &before.info,
+                                after: &account.info,
+                            },
+                        );
+                    }
+                }
+                Err(error) => notify(
+                    &mut self.observers,
+                    &Event::Rejected {
+                        transaction,
+                        account: &account.info,
+                        error,
+                    },
+                ),
+            }
+            for entry in before.status_changes(account) {
+                notify(&mut self.observers, &Event::StatusChanged(entry));
+            }
+        }
+        result
+    }
+
+    // the risk rules, the account of the client and the dispute deadline
+    fn apply_to_account(
+        &mut self,
+        transaction: &Transaction,
+        client_id: ClientID,
+    ) -> Result<(), Error> {
+        let freezes = self.check_risk(transaction)?;
+        let account = self
+            .accounts
+            .get_mut(&client_id)
+            .expect("the account is created above");
+        let before = self
+            .audit
+            .then(|| AccountSnapshot::take(account, transaction.id()));
+        let gauges = dispute_gauges(account);
+        let result = account.process_transaction(transaction);
+        let (open_disputes, held) = dispute_gauges(account);
+        self.metrics
+            .adjust(open_disputes - gauges.0, held - gauges.1);
+        if let Some(before) = before {
+            let flow = check_account(
+                &before,
+                account,
+                transaction,
+                result.is_ok(),
+                true,
+                &self.config,
+            )?;
+            self.ledger += flow as f64;
+        }
+        result?;
+        for decision in freezes {
+            // an account that isn't active any more is left as it is, and nothing is recorded
+            if account.freeze_by_rule(
+                transaction,
+                format!("risk rule {}: {}", decision.rule, decision.reason),
+            ) {
+                warn!(rule = %decision.rule, action = ?decision.action, reason = %decision.reason, "risk rule fired");
+                self.risk_decisions.push(decision);
+            }
+        }
+        self.risk_rules
+            .iter_mut()
+            .for_each(|rule| rule.observe(transaction));
+        if let (TransactionType::Dispute, Some(max_open), Some(now)) = (
+            transaction.t_type(),
+            self.config.dispute_window.max_open,
+            transaction.timestamp(),
+        ) {
+            self.dispute_deadlines.push(Reverse((
+                now.saturating_add(max_open),
+                transaction.id(),
+                client_id,
+            )));
+        }
+        Ok(())
+    }
+
+    /// settle every dispute whose deadline is before `now`
+    /// returns how many of them were settled, see `expired_disputes`
+    /// in audit mode, a settlement that breaks an invariant stops it, and the next
+    /// `process_transaction` returns the violation
+    pub fn expire_disputes(&mut self, now: Timestamp) -> usize {
+        let (settled, result) = self.settle_expired(now);
+        if let Err(violation) = result {
+            error!(error = %violation, "invariant violated");
+            self.violation = Some(violation);
+        }
+        settled
+    }
+
+    // how many disputes were settled, and the violation that stopped it in audit mode
+    fn settle_expired(&mut self, now: Timestamp) -> (usize, Result<(), Error>) {
+        let mut settled = 0;
+        while let Some(Reverse((deadline, tx, client_id))) = self.dispute_deadlines.peek().copied()
+        {
+            if deadline >= now {
+                break;
+            }
+            self.dispute_deadlines.pop();
+            let account = self
+                .accounts
+                .get_mut(&client_id)
+                .expect("only the accounts with a dispute have a deadline");
+            let before = self.audit.then(|| AccountSnapshot::take(account, tx));
+            let observed = (!self.observers.is_empty()).then(|| Observed::take(account));
+            let gauges = dispute_gauges(account);
+            if let Some(settlement) = account.expire_dispute(tx, now) {
+                let (open_disputes, held) = dispute_gauges(account);
+                self.metrics
+                    .adjust(open_disputes - gauges.0, held - gauges.1);
+                self.metrics.record_expiry(settlement.t_type());
+                info!(
+                    client = client_id,
+                    tx,
+                    settlement = ?settlement.t_type(),
+                    "settled an expired dispute"
+                );
+                settled += 1;
+                if let Some(before) = before {
+                    // the status policy doesn't apply to an expired dispute
+                    match check_account(&before, account, &settlement, true, false, &self.config) {
+                        Ok(flow) => self.ledger += flow as f64,
+                        Err(violation) => {
+                            self.expired_disputes.push(settlement);
+                            return (settled, Err(violation));
+                        }
+                    }
+                }
+                if let Some(observed) = observed {
+                    notify(
+                        &mut self.observers,
+                        &Event::DisputeExpired {
+                            settlement: &settlement,
+                            before: &observed.info,
+                            after: &account.info,
+                        },
+                    );
+                    for entry in observed.status_changes(account) {
+                        notify(&mut self.observers, &Event::StatusChanged(entry));
+                    }
+                }
+                self.expired_disputes.push(settlement);
+            }
+        }
+        (settled, Ok(()))
+    }
+
+    /// the resolves and chargebacks made up for the expired disputes since they were last taken
+    pub fn expired_disputes(&self) -> &[Transaction] {
+        &self.expired_disputes
+    }
+
+    /// take the settlements of the expired disputes out of the engine, e.g. to report them as
+    /// they come on a long input, they pile up until then
+    pub fn take_expired_disputes(&mut self) -> Vec<Transaction> {
+        std::mem::take(&mut self.expired_disputes)
+    }
+
+    /// restore an account from its balances, e.g. from a snapshot
+    /// the disputes that were open when the snapshot was taken can't be resolved later on
+    pub fn restore_account(&mut self, info: ClientAccountInfo) {
+        let client_id = info.client;
+        let mut account = self.new_account(client_id);
+        account.info = info;
+        self.metrics.adjust(0, account.info.held);
+        if self.audit {
+            self.ledger += net_funds(&account.info, &self.config) as f64;
+        }
+        match self.accounts.insert(client_id, account) {
+            Some(replaced) => {
+                self.metrics
+                    .adjust(-(replaced.open_dispute_count() as i64), -replaced.info.held);
+                if self.audit {
+                    self.ledger -= net_funds(&replaced.info, &self.config) as f64;
+                }
+            }
+            None => self.insertion_order.push(client_id),
+        }
+    }
+
+    pub fn account(&self, client_id: ClientID) -> Option<&ClientAccount> {
+        self.accounts.get(&client_id)
+    }
+
+    /// the status changes of every account, ordered by client id
+    pub fn audit_trail(&self) -> Vec<&AuditEntry> {
+        self.accounts(OutputOrder::ClientId)
+            .iter()
+            .flat_map(|info| self.accounts[&info.client].audit_trail())
+            .collect()
+    }
+
+    /// the statement of the open disputes of every account, ordered by client id
+    pub fn open_disputes(&self) -> Vec<DisputeInfo> {
+        self.accounts(OutputOrder::ClientId)
+            .iter()
+            .flat_map(|info| self.accounts[&info.client].open_disputes())
+            .collect()
+    }
+
+    /// the chargeback losses of every account per reason code
+    pub fn chargeback_report(&self) -> ChargebackReport {
+        let mut report = ChargebackReport::new();
+        for account in self.accounts.values() {
+            account
+                .chargebacks()
+                .iter()
+                .for_each(|chargeback| report.record(chargeback));
+        }
+        report
+    }
+
+    /// the accounts with negative available or total funds, ordered by client id
+    pub fn overdrawn_accounts(&self) -> Vec<&ClientAccount> {
+        self.accounts(OutputOrder::ClientId)
+            .iter()
+            .map(|info| &self.accounts[&info.client])
+            .filter(|account| account.info.available < 0f32 || account.info.total < 0f32)
+            .collect()
+    }
+
+    /// the accounts that owe money beyond their overdraft limit, ordered by client id
+    pub fn accounts_in_debt(&self) -> Vec<&ClientAccountInfo> {
+        self.accounts(OutputOrder::ClientId)
+            .into_iter()
+            .filter(|info| info.in_debt())
+            .collect()
+    }
+
+    /// the information of all accounts in the given order
+    pub fn accounts(&self, order: OutputOrder) -> Vec<&ClientAccountInfo> {
+        let mut records: Vec<&ClientAccountInfo> = self
+            .insertion_order
+            .iter()
+            .map(|client_id| &self.accounts[client_id].info)
+            .collect();
+        match order {
+            OutputOrder::ClientId => records.sort_by_key(|info| info.client),
+            // ties are broken by the client id to keep the output stable
+            OutputOrder::TotalDesc => records.sort_by(|a, b| {
+                b.total
+                    .total_cmp(&a.total)
+                    .then_with(|| a.client.cmp(&b.client))
+            }),
+            OutputOrder::Insertion => {}
+        }
+        records
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        AccountStatus, ClientAccountInfo, DebtPolicy, DisputeExpiryAction, Engine, EngineConfig,
+        Error, InputFormat, OutputFormat, OutputOrder, RiskAction, RiskRule, Transaction,
+        TransactionType, WithdrawalDisputeMode,
+    };
+    use std::fs::File;
+
+    fn deposit(client: u16, tx: u32, amount: f32) -> Transaction {
+        Transaction::new(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    fn clients(engine: &Engine, order: OutputOrder) -> Vec<u16> {
+        engine
+            .accounts(order)
+            .iter()
+            .map(|info| info.client)
+            .collect()
+    }
+
+    #[test]
+    fn test_output_order() {
+        let mut engine = Engine::new();
+        engine.process_transaction(&deposit(3, 1, 1.0)).unwrap();
+        engine.process_transaction(&deposit(1, 2, 5.0)).unwrap();
+        engine.process_transaction(&deposit(2, 3, 1.0)).unwrap();
+        engine.process_transaction(&deposit(3, 4, 9.0)).unwrap();
+
+        assert_eq!(clients(&engine, OutputOrder::ClientId), vec![1, 2, 3]);
+        assert_eq!(clients(&engine, OutputOrder::TotalDesc), vec![3, 1, 2]);
+        assert_eq!(clients(&engine, OutputOrder::Insertion), vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn test_identical_input_gives_identical_output() {
+        let run = || {
+            let mut engine = Engine::new();
+            let f = File::open("./sample_csv/large_dataset_with_different_clients.csv").unwrap();
+            for transaction in InputFormat::Csv.reader(f) {
+                let _ = engine.process_transaction(&transaction.unwrap());
+            }
+            let mut data = vec![];
+            {
+                let mut writer = OutputFormat::Csv.writer(&mut data, 4);
+                for record in engine.accounts(OutputOrder::default()) {
+                    writer.write(record).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+            data
+        };
+        assert_eq!(run(), run());
+    }
+
+    fn at(t_type: TransactionType, tx: u32, amount: Option<f32>, timestamp: u64) -> Transaction {
+        Transaction::new(t_type, 1, tx, amount).with_timestamp(timestamp)
+    }
+
+    fn engine_with_window(max_age: Option<u64>, on_expiry: DisputeExpiryAction) -> Engine {
+        let mut config = EngineConfig::default();
+        config.dispute_window.max_age = max_age;
+        config.dispute_window.max_open = Some(10);
+        config.dispute_window.on_expiry = on_expiry;
+        Engine::with_config(config)
+    }
+
+    #[test]
+    fn test_dispute_too_old() {
+        let mut engine = engine_with_window(Some(100), DisputeExpiryAction::Resolve);
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 1, Some(5.0), 0))
+            .unwrap();
+        let result = engine.process_transaction(&at(TransactionType::Dispute, 1, None, 101));
+        assert!(matches!(
+            result,
+            Err(Error::DisputeWindowExpired {
+                tx: 1,
+                age: 101,
+                ..
+            })
+        ));
+        engine
+            .process_transaction(&at(TransactionType::Dispute, 1, None, 100))
+            .unwrap();
+        // without a timestamp there is no window
+        engine
+            .process_transaction(&Transaction::new(TransactionType::Deposit, 1, 2, Some(1.0)))
+            .unwrap();
+        engine
+            .process_transaction(&at(TransactionType::Dispute, 2, None, 1000))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_expired_dispute_is_resolved() {
+        let mut engine = engine_with_window(None, DisputeExpiryAction::Resolve);
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 1, Some(5.0), 0))
+            .unwrap();
+        engine
+            .process_transaction(&at(TransactionType::Dispute, 1, None, 5))
+            .unwrap();
+        // the deadline is 15, it isn't passed yet
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 2, Some(1.0), 15))
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().info.held, 5.0);
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 3, Some(1.0), 16))
+            .unwrap();
+        let info = &engine.account(1).unwrap().info;
+        assert_eq!((info.available, info.held, info.total), (7.0, 0.0, 7.0));
+        assert_eq!(engine.expired_disputes().len(), 1);
+        assert_eq!(
+            engine.expired_disputes()[0].t_type(),
+            TransactionType::Resolve
+        );
+        // the settlement shows up in the metrics, the gauges follow it
+        let metrics = engine.metrics().snapshot();
+        assert_eq!(metrics.expired_disputes[&TransactionType::Resolve], 1);
+        assert_eq!((metrics.open_disputes, metrics.held), (0, 0.0));
+        assert_eq!(engine.take_expired_disputes().len(), 1);
+        assert!(engine.expired_disputes().is_empty());
+        // the late resolve doesn't find the dispute anymore
+        let result = engine.process_transaction(&at(TransactionType::Resolve, 1, None, 17));
+        assert!(matches!(result, Err(Error::NotFoundTransaction { .. })));
+    }
+
+    #[test]
+    fn test_expired_dispute_is_charged_back() {
+        let mut engine = engine_with_window(None, DisputeExpiryAction::Chargeback);
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 1, Some(5.0), 0))
+            .unwrap();
+        engine
+            .process_transaction(&at(TransactionType::Dispute, 1, None, 5))
+            .unwrap();
+        // resolved in time and disputed again, the first deadline doesn't count anymore
+        engine
+            .process_transaction(&at(TransactionType::Resolve, 1, None, 10))
+            .unwrap();
+        engine
+            .process_transaction(&at(TransactionType::Dispute, 1, None, 12))
+            .unwrap();
+        assert_eq!(engine.expire_disputes(20), 0);
+        assert_eq!(engine.expire_disputes(23), 1);
+        let account = engine.account(1).unwrap();
+        assert_eq!((account.info.total, account.info.held), (0.0, 0.0));
+        assert_eq!(account.info.status, AccountStatus::Locked);
+        assert_eq!(engine.audit_trail().len(), 1);
+    }
+
+    #[test]
+    fn test_dispute_statement_and_chargeback_report() {
+        let mut engine = Engine::new();
+        engine.process_transaction(&deposit(1, 1, 5.0)).unwrap();
+        engine.process_transaction(&deposit(2, 2, 3.0)).unwrap();
+        engine.process_transaction(&deposit(3, 3, 4.0)).unwrap();
+        let disputes = [
+            Transaction::new(TransactionType::Dispute, 1, 1, Some(2.0))
+                .with_reason_code("fraud")
+                .with_reason("card stolen"),
+            Transaction::new(TransactionType::Dispute, 2, 2, None).with_reason_code("fraud"),
+            Transaction::new(TransactionType::Dispute, 3, 3, None),
+        ];
+        for dispute in &disputes {
+            engine.process_transaction(dispute).unwrap();
+        }
+        let statement = engine.open_disputes();
+        assert_eq!(statement.len(), 3);
+        assert_eq!(statement[0].amount, 2.0);
+        assert_eq!(statement[0].reason_code.as_deref(), Some("fraud"));
+        assert_eq!(statement[0].note.as_deref(), Some("card stolen"));
+        assert_eq!(statement[2].reason_code, None);
+        let metrics = engine.metrics().snapshot();
+        assert_eq!((metrics.open_disputes, metrics.held), (3, 9.0));
+
+        for (client, tx) in [(1, 1), (2, 2), (3, 3)] {
+            engine
+                .process_transaction(&Transaction::new(
+                    TransactionType::Chargeback,
+                    client,
+                    tx,
+                    None,
+                ))
+                .unwrap();
+        }
+        assert!(engine.open_disputes().is_empty());
+        let metrics = engine.metrics().snapshot();
+        assert_eq!((metrics.open_disputes, metrics.held), (0, 0.0));
+        assert_eq!(metrics.accepted[&TransactionType::Chargeback], 3);
+        let losses = engine.chargeback_report().losses();
+        assert_eq!(losses.len(), 2);
+        assert_eq!((losses[0].count, losses[0].amount), (2, 5.0));
+        assert_eq!(losses[1].reason_code, "unspecified");
+        assert_eq!(losses[1].amount, 4.0);
+    }
+
+    #[test]
+    fn test_overdraft_limit_per_client() {
+        let mut config = EngineConfig::default();
+        config.overdraft.clients.insert(String::from("2"), 10.0);
+        let mut engine = Engine::with_config(config);
+        for client in [1, 2, 3] {
+            let withdrawal = Transaction::new(
+                TransactionType::Withdrawal,
+                client,
+                client as u32,
+                Some(5.0),
+            );
+            let result = engine.process_transaction(&withdrawal);
+            assert_eq!(result.is_ok(), client == 2, "client {}", client);
+            if client == 3 {
+                // no limit in the config, but the overdraft row gives one
+                let overdraft = Transaction::new(TransactionType::Overdraft, 3, 10, Some(5.0));
+                engine.process_transaction(&overdraft).unwrap();
+                engine.process_transaction(&withdrawal).unwrap();
+            }
+        }
+        let overdrawn: Vec<(u16, f32)> = engine
+            .overdrawn_accounts()
+            .iter()
+            .map(|account| (account.info.client, account.info.available))
+            .collect();
+        assert_eq!(overdrawn, vec![(2, -5.0), (3, -5.0)]);
+        assert_eq!(engine.account(2).unwrap().overdraft_limit(), 10.0);
+    }
+
+    // a custom rule that rejects every deposit of client 9
+    struct BlockClient;
+
+    impl RiskRule for BlockClient {
+        fn id(&self) -> &str {
+            "block-9"
+        }
+
+        fn check(
+            &mut self,
+            transaction: &Transaction,
+            _account: &ClientAccountInfo,
+        ) -> Option<(RiskAction, String)> {
+            (transaction.client_id() == 9)
+                .then(|| (RiskAction::Reject, String::from("blocked client")))
+        }
+    }
+
+    #[test]
+    fn test_risk_rules() {
+        let config = EngineConfig::from_toml(
+            r#"
+            [[risk_rules]]
+            rule = "max_withdrawal"
+            id = "big-withdrawal"
+            limit = 100.0
+
+            [[risk_rules]]
+            rule = "dispute_ratio"
+            id = "many-disputes"
+            max_ratio = 0.5
+            min_transactions = 2
+            "#,
+        )
+        .unwrap();
+        let mut engine = Engine::with_config(config);
+        engine.add_risk_rule(Box::new(BlockClient));
+        engine.process_transaction(&deposit(1, 1, 500.0)).unwrap();
+        engine.process_transaction(&deposit(1, 2, 1.0)).unwrap();
+        let withdrawal = Transaction::new(TransactionType::Withdrawal, 1, 3, Some(150.0));
+        let result = engine.process_transaction(&withdrawal);
+        assert!(
+            matches!(result, Err(Error::RiskRejected { rule, .. }) if rule == "big-withdrawal")
+        );
+        assert_eq!(engine.account(1).unwrap().info.available, 501.0);
+
+        // the second dispute out of 2 deposits goes through, and freezes the account
+        for tx in [1, 2] {
+            engine
+                .process_transaction(&Transaction::new(TransactionType::Dispute, 1, tx, None))
+                .unwrap();
+        }
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.info.held, 501.0);
+        assert_eq!(account.info.status, AccountStatus::Frozen);
+        assert_eq!(
+            account.audit_trail()[0].reason.as_deref(),
+            Some("risk rule many-disputes: 2 disputes for 2 transactions, above the ratio 0.5")
+        );
+
+        let result = engine.process_transaction(&deposit(9, 4, 1.0));
+        assert!(matches!(result, Err(Error::RiskRejected { .. })));
+        let decisions: Vec<(&str, RiskAction)> = engine
+            .risk_decisions()
+            .iter()
+            .map(|decision| (decision.rule.as_str(), decision.action))
+            .collect();
+        assert_eq!(
+            decisions,
+            vec![
+                ("big-withdrawal", RiskAction::Reject),
+                ("many-disputes", RiskAction::Freeze),
+                ("block-9", RiskAction::Reject)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_audit_mode_on_the_samples() {
+        for (sample, config) in [
+            ("all_transactions", EngineConfig::default()),
+            ("admin_operations", EngineConfig::default()),
+            ("locked_account", EngineConfig::default()),
+            ("wrong_dispute", EngineConfig::default()),
+            (
+                "large_dataset_with_different_clients",
+                EngineConfig {
+                    withdrawal_dispute: WithdrawalDisputeMode::HeldCredit,
+                    debt: DebtPolicy { repay_first: true },
+                    ..EngineConfig::default()
+                },
+            ),
+        ] {
+            let mut engine = Engine::with_config(config);
+            engine.enable_audit();
+            let f = File::open(format!("./sample_csv/{}.csv", sample)).unwrap();
+            for transaction in InputFormat::Csv.reader(f) {
+                let result = engine.process_transaction(&transaction.unwrap());
+                assert!(
+                    !matches!(result, Err(Error::InvariantViolation { .. })),
+                    "{}: {:?}",
+                    sample,
+                    result
+                );
+            }
+            engine.check_ledger().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_ledger() {
+        let mut engine = engine_with_window(None, DisputeExpiryAction::Chargeback);
+        engine
+            .process_transaction(&at(TransactionType::Deposit, 1, Some(5.0), 0))
+            .unwrap();
+        // the ledger starts from the funds already there
+        engine.enable_audit();
+        for transaction in [
+            at(TransactionType::Deposit, 2, Some(3.0), 1),
+            at(TransactionType::Dispute, 2, Some(1.0), 2),
+            // the dispute expires and is charged back first
+            Transaction::new(TransactionType::Deposit, 2, 3, Some(2.0)).with_timestamp(20),
+        ] {
+            engine.process_transaction(&transaction).unwrap();
+        }
+        assert_eq!(engine.expired_disputes().len(), 1);
+        engine.check_ledger().unwrap();
+        assert_eq!(engine.ledger, 9.0);
+
+        // money that showed up behind the back of the engine
+        engine.accounts.get_mut(&1).unwrap().info.available += 1.0;
+        engine.accounts.get_mut(&1).unwrap().info.total += 1.0;
+        assert!(matches!(
+            engine.check_ledger(),
+            Err(Error::LedgerMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+use csv::ByteRecord;
+use std::{
+    fmt,
+    io::{BufRead, BufReader, Lines, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    build_csv_reader, decompress_reader, open_input, ClientAccountInfo, Compression, Error,
+    Transaction,
+};
+
+/// a stream of parsed transactions, no matter which format they come from
+pub type TransactionStream<'a> = Box<dyn Iterator<Item = Result<Transaction, Error>> + 'a>;
+
+/// the supported formats of the transaction input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    // one json object per line
+    Jsonl,
+}
+
+impl InputFormat {
+    /// guess the format from the file extension, None if the extension is unknown
+    /// the compression extension is skipped, so `a.csv.gz` is still a csv file
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = match Compression::from_path(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem()?),
+        };
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(InputFormat::Csv),
+            "jsonl" | "ndjson" => Some(InputFormat::Jsonl),
+            _ => None,
+        }
+    }
+
+    /// build a transaction reader of this format on top of the stream
+    pub fn reader<'a, R: Read + 'a>(self, stream_reader: R) -> TransactionStream<'a> {
+        match self {
+            InputFormat::Csv => Box::new(CsvTransactionReader::new(stream_reader)),
+            InputFormat::Jsonl => Box::new(JsonlTransactionReader::new(stream_reader)),
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            _ => Err(Error::UnknownFormat {
+                format: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// one input of a run, either a file or stdin(given as `-` on the command line)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputSource {
+    Stdin,
+    File(PathBuf),
+}
+
+impl InputSource {
+    pub fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            InputSource::Stdin
+        } else {
+            InputSource::File(PathBuf::from(arg))
+        }
+    }
+
+    /// the format given by the flag, then by the file extension, and csv is the fallback
+    pub fn format(&self, flag: Option<InputFormat>) -> InputFormat {
+        flag.or_else(|| match self {
+            InputSource::Stdin => None,
+            InputSource::File(path) => InputFormat::from_path(path),
+        })
+        .unwrap_or(InputFormat::Csv)
+    }
+
+    /// open the input, compressed input is decompressed on the fly
+    pub fn open(&self) -> Result<Box<dyn Read>, Error> {
+        match self {
+            InputSource::Stdin => {
+                decompress_reader(std::io::stdin(), Compression::None).map_err(|err| {
+                    Error::OpenInput {
+                        path: self.to_string(),
+                        reason: err.to_string(),
+                    }
+                })
+            }
+            InputSource::File(path) => open_input(path),
+        }
+    }
+}
+
+impl fmt::Display for InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputSource::Stdin => write!(f, "<stdin>"),
+            InputSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// read the balances back from the csv output, e.g. a snapshot or the output of another system
+pub fn read_balances<R: Read>(stream_reader: R) -> Result<Vec<ClientAccountInfo>, Error> {
+    build_csv_reader(stream_reader)
+        .deserialize()
+        .map(|record| {
+            record.map_err(|err: csv::Error| Error::ParseRecord {
+                line: err.position().map_or(0, |position| position.line()),
+                reason: err.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// read transactions from a csv stream one record at a time
+pub struct CsvTransactionReader<R: Read> {
+    reader: csv::Reader<R>,
+    headers: Option<ByteRecord>,
+    raw_record: ByteRecord,
+    // stop reading after an error that can't be recovered, e.g. io error
+    done: bool,
+}
+
+impl<R: Read> CsvTransactionReader<R> {
+    pub fn new(stream_reader: R) -> Self {
+        Self {
+            reader: build_csv_reader(stream_reader),
+            headers: None,
+            raw_record: ByteRecord::new(),
+            done: false,
+        }
+    }
+
+    fn parse_error(&mut self, err: csv::Error) -> Error {
+        if err.is_io_error() {
+            self.done = true;
+        }
+        Error::ParseRecord {
+            line: err.position().map_or(0, |position| position.line()),
+            reason: err.to_string(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for CsvTransactionReader<R> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.headers.is_none() {
+            match self.reader.byte_headers() {
+                Ok(headers) => self.headers = Some(headers.clone()),
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(self.parse_error(err)));
+                }
+            }
+        }
+        match self.reader.read_byte_record(&mut self.raw_record) {
+            Ok(true) => {
+                let line = self
+                    .raw_record
+                    .position()
+                    .map_or(0, |position| position.line());
+                Some(
+                    self.raw_record
+                        .deserialize(self.headers.as_ref())
+                        .map_err(|err| Error::ParseRecord {
+                            line,
+                            reason: err.to_string(),
+                        }),
+                )
+            }
+            Ok(false) => None,
+            Err(err) => Some(Err(self.parse_error(err))),
+        }
+    }
+}
+
+/// read transactions from a json lines stream, blank lines are skipped
+pub struct JsonlTransactionReader<R: Read> {
+    lines: Lines<BufReader<R>>,
+    line: u64,
+    // stop reading after the underlying stream is broken
+    done: bool,
+}
+
+impl<R: Read> JsonlTransactionReader<R> {
+    pub fn new(stream_reader: R) -> Self {
+        Self {
+            lines: BufReader::new(stream_reader).lines(),
+            line: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for JsonlTransactionReader<R> {
+    type Item = Result<Transaction, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            self.line += 1;
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(Error::ParseRecord {
+                        line: self.line,
+                        reason: err.to_string(),
+                    }));
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str(&line).map_err(|err| Error::ParseRecord {
+                    line: self.line,
+                    reason: err.to_string(),
+                }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{read_balances, Error, InputFormat, InputSource, Transaction};
+    use std::{fs::File, path::Path};
+
+    fn read_all(path: &str, format: InputFormat) -> Vec<Transaction> {
+        let f = File::open(path).unwrap();
+        format.reader(f).map(|t| t.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("a/b.csv")),
+            Some(InputFormat::Csv)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("b.JSONL")),
+            Some(InputFormat::Jsonl)
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("b.jsonl.zst")),
+            Some(InputFormat::Jsonl)
+        );
+        assert_eq!(InputFormat::from_path(Path::new("b.txt")), None);
+        assert_eq!(InputFormat::from_path(Path::new("b.gz")), None);
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_input_source() {
+        assert_eq!(InputSource::from_arg("-"), InputSource::Stdin);
+        assert_eq!(InputSource::Stdin.format(None), InputFormat::Csv);
+        assert_eq!(
+            InputSource::Stdin.format(Some(InputFormat::Jsonl)),
+            InputFormat::Jsonl
+        );
+        let source = InputSource::from_arg("./sample_csv/all_transactions.jsonl");
+        assert_eq!(source.format(None), InputFormat::Jsonl);
+        assert_eq!(source.to_string(), "./sample_csv/all_transactions.jsonl");
+        assert!(InputSource::from_arg("./sample_csv/missing.csv")
+            .open()
+            .is_err());
+    }
+
+    #[test]
+    fn test_csv_and_jsonl_yield_same_transactions() {
+        let csv = read_all("./sample_csv/all_transactions.csv", InputFormat::Csv);
+        let jsonl = read_all("./sample_csv/all_transactions.jsonl", InputFormat::Jsonl);
+        assert_eq!(csv.len(), 9);
+        assert_eq!(format!("{:?}", csv), format!("{:?}", jsonl));
+    }
+
+    #[test]
+    fn test_read_balances() {
+        let data = "client,available,held,total,status\n1,1.5000,0.5000,2.0000,active\n2,0.0000,0.0000,0.0000,locked\n";
+        let balances = read_balances(data.as_bytes()).unwrap();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].held, 0.5);
+        assert!(balances[1].is_locked());
+        assert!(read_balances("client,available\n1,x\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_record_is_reported_with_line() {
+        let data = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 1.0}\n\n{\"type\": \"unknown\"}\n";
+        let result: Vec<_> = InputFormat::Jsonl.reader(data.as_bytes()).collect();
+        assert!(result[0].is_ok());
+        assert!(matches!(result[1], Err(Error::ParseRecord { line: 3, .. })));
+
+        let data = "type, client, tx, amount\ndeposit, 1, 1, 1.0\nsteal, 1, 2, 1.0\n";
+        let result: Vec<_> = InputFormat::Csv.reader(data.as_bytes()).collect();
+        assert!(result[0].is_ok());
+        assert!(matches!(result[1], Err(Error::ParseRecord { line: 3, .. })));
+    }
+}
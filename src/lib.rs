@@ -1,10 +1,58 @@
 use csv::Reader;
 use snafu::Snafu;
 use std::{collections::HashMap, io::Read};
+mod audit;
 mod client;
+mod compliance;
+mod compression;
+mod config;
+mod diff;
+mod dispute;
+mod engine;
+mod input;
+mod merge;
+mod metrics;
+mod observer;
+mod output;
+mod pipeline;
+mod report;
+mod risk;
+mod stats;
 mod transaction;
-pub use client::{ClientAccount, ClientAccountInfo};
-pub use transaction::{Amount, ClientID, Transaction, TransactionID, TransactionType};
+pub use client::{AccountStatus, AuditEntry, ClientAccount, ClientAccountInfo};
+pub use compliance::{
+    ComplianceConfig, ComplianceFlag, ComplianceKind, ComplianceMonitor, ComplianceRecord,
+};
+pub use compression::{decompress_reader, open_input, Compression};
+pub use config::{
+    DebtPolicy, DisputeBelowLimit, DisputeExpiryAction, DisputeLimits, DisputeWindow, EngineConfig,
+    OverdraftPolicy, StatusPolicy, WithdrawalDisputeMode,
+};
+pub use diff::{diff_accounts, AccountDiff, DiffField, DiffFormat, DiffKind, DiffRecord};
+pub use dispute::{
+    ChargebackLoss, ChargebackLossRecord, ChargebackRecord, ChargebackReport, DisputeEvent,
+    DisputeInfo, DisputeRecord,
+};
+pub use engine::Engine;
+pub use input::{
+    read_balances, CsvTransactionReader, InputFormat, InputSource, JsonlTransactionReader,
+    TransactionStream,
+};
+pub use merge::MergeReader;
+pub use metrics::{serve_metrics, Histogram, Metrics, MetricsRegistry};
+pub use observer::{Event, Observer};
+pub use output::{
+    format_amount, AccountWriter, CsvAccountWriter, JsonAccountWriter, JsonlAccountWriter,
+    OutputFormat, OutputOrder, DEFAULT_PRECISION,
+};
+pub use pipeline::{process_inputs, InputOptions};
+pub use report::Rejection;
+pub use risk::{
+    DisputeRatio, MaxWithdrawal, RiskAction, RiskDecision, RiskRule, RiskRuleConfig,
+    WithdrawalVelocity,
+};
+pub use stats::ProcessingStats;
+pub use transaction::{Amount, ClientID, Timestamp, Transaction, TransactionID, TransactionType};
 
 // the error type used in the program
 #[derive(Debug, Snafu)]
@@ -27,8 +75,92 @@ pub enum Error {
         client
     ))]
     AccountLocked { client: ClientID },
+    #[snafu(display("can't withdraw from client {}, because the account is frozen", client))]
+    AccountFrozen { client: ClientID },
+    #[snafu(display(
+        "can't process transaction for client {}, because the account is closed",
+        client
+    ))]
+    AccountClosed { client: ClientID },
+    #[snafu(display("the account of client {} can't go from {} to {}", client, from, to))]
+    InvalidStatusChange {
+        client: ClientID,
+        from: AccountStatus,
+        to: AccountStatus,
+    },
+    #[snafu(display(
+        "the account of client {} can't be closed with total {}",
+        client,
+        total
+    ))]
+    AccountNotEmpty { client: ClientID, total: Amount },
+    #[snafu(display("transaction {} of client {} needs a reason", tx, client))]
+    MissingReason { client: ClientID, tx: TransactionID },
     #[snafu(display("can't find transaction {} in client {} account", tx, client))]
     NotFoundTransaction { client: ClientID, tx: TransactionID },
+    #[snafu(display("withdrawal {} of client {} can't be disputed", tx, client))]
+    DisputeNotAllowed { client: ClientID, tx: TransactionID },
+    #[snafu(display(
+        "transaction {} of client {} is {} old, too old to be disputed",
+        tx,
+        client,
+        age
+    ))]
+    DisputeWindowExpired {
+        client: ClientID,
+        tx: TransactionID,
+        age: Timestamp,
+    },
+    #[snafu(display(
+        "can't cover {} of transaction {} of client {}, only {} is left",
+        amount,
+        tx,
+        client,
+        remaining
+    ))]
+    DisputeExceedsAmount {
+        client: ClientID,
+        tx: TransactionID,
+        amount: Amount,
+        remaining: Amount,
+    },
+    #[snafu(display(
+        "transaction {} of client {} has been disputed {} times already",
+        tx,
+        client,
+        limit
+    ))]
+    TransactionDisputeLimit {
+        client: ClientID,
+        tx: TransactionID,
+        limit: u32,
+    },
+    #[snafu(display(
+        "client {} reached the limit of {} disputes, transaction {} can't be disputed",
+        client,
+        limit,
+        tx
+    ))]
+    ClientDisputeLimit {
+        client: ClientID,
+        tx: TransactionID,
+        limit: u32,
+    },
+    #[snafu(display(
+        "dispute {} would take client {} to {}, below the overdraft limit {}",
+        tx,
+        client,
+        available,
+        limit
+    ))]
+    OverdraftLimitExceeded {
+        client: ClientID,
+        tx: TransactionID,
+        available: Amount,
+        limit: Amount,
+    },
+    #[snafu(display("withdrawal {} of client {} can't be charged back", tx, client))]
+    ChargebackNotAllowed { client: ClientID, tx: TransactionID },
     #[snafu(display("the transaction:\nclient: {}\ntx: {}\ntype: {:?}\namount: {:?}\n is not valid, please check the record", client, tx, t_type, amount))]
     InvalidTransaction {
         client: ClientID,
@@ -36,6 +168,109 @@ pub enum Error {
         t_type: TransactionType,
         amount: Option<Amount>,
     },
+    #[snafu(display("can't parse the record at line {}: {}", line, reason))]
+    ParseRecord { line: u64, reason: String },
+    #[snafu(display("unknown input format {}", format))]
+    UnknownFormat { format: String },
+    #[snafu(display("unknown output order {}", order))]
+    UnknownOrder { order: String },
+    #[snafu(display("can't open the input {}: {}", path, reason))]
+    OpenInput { path: String, reason: String },
+    #[snafu(display(
+        "transaction {} has timestamp {}, earlier than the previous {} in the same stream",
+        tx,
+        timestamp,
+        previous
+    ))]
+    OutOfOrder {
+        tx: TransactionID,
+        timestamp: Timestamp,
+        previous: Timestamp,
+    },
+    #[snafu(display("transaction {} has no timestamp to merge on", tx))]
+    MissingTimestamp { tx: TransactionID },
+    #[snafu(display(
+        "transaction {} of client {} rejected by risk rule {}: {}",
+        tx,
+        client,
+        rule,
+        reason
+    ))]
+    RiskRejected {
+        client: ClientID,
+        tx: TransactionID,
+        rule: String,
+        reason: String,
+    },
+    #[snafu(display(
+        "invariant violated by transaction {} of client {}: {}",
+        tx,
+        client,
+        reason
+    ))]
+    InvariantViolation {
+        client: ClientID,
+        tx: TransactionID,
+        reason: String,
+    },
+    #[snafu(display(
+        "the accounts hold {} in total, the transactions brought in {}",
+        funds,
+        expected
+    ))]
+    LedgerMismatch { funds: f64, expected: f64 },
+    #[snafu(display("invalid config: {}", reason))]
+    InvalidConfig { reason: String },
+    #[snafu(display("can't write the output: {}", reason))]
+    WriteOutput { reason: String },
+}
+
+impl Error {
+    /// whether the record itself is broken(unreadable, out of order or with a wrong amount),
+    /// rather than refused by the rules of the engine, e.g. for lack of funds
+    pub fn is_format_error(&self) -> bool {
+        matches!(
+            self,
+            Error::ParseRecord { .. }
+                | Error::OutOfOrder { .. }
+                | Error::MissingTimestamp { .. }
+                | Error::InvalidTransaction { .. }
+        )
+    }
+
+    /// the name of the variant, to count the errors of a kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::WrongAccount => "wrong_account",
+            Error::NoSufficientFunds { .. } => "no_sufficient_funds",
+            Error::AccountLocked { .. } => "account_locked",
+            Error::AccountFrozen { .. } => "account_frozen",
+            Error::AccountClosed { .. } => "account_closed",
+            Error::InvalidStatusChange { .. } => "invalid_status_change",
+            Error::AccountNotEmpty { .. } => "account_not_empty",
+            Error::MissingReason { .. } => "missing_reason",
+            Error::NotFoundTransaction { .. } => "not_found_transaction",
+            Error::DisputeNotAllowed { .. } => "dispute_not_allowed",
+            Error::DisputeWindowExpired { .. } => "dispute_window_expired",
+            Error::DisputeExceedsAmount { .. } => "dispute_exceeds_amount",
+            Error::TransactionDisputeLimit { .. } => "transaction_dispute_limit",
+            Error::ClientDisputeLimit { .. } => "client_dispute_limit",
+            Error::OverdraftLimitExceeded { .. } => "overdraft_limit_exceeded",
+            Error::ChargebackNotAllowed { .. } => "chargeback_not_allowed",
+            Error::InvalidTransaction { .. } => "invalid_transaction",
+            Error::ParseRecord { .. } => "parse_record",
+            Error::UnknownFormat { .. } => "unknown_format",
+            Error::UnknownOrder { .. } => "unknown_order",
+            Error::OpenInput { .. } => "open_input",
+            Error::OutOfOrder { .. } => "out_of_order",
+            Error::MissingTimestamp { .. } => "missing_timestamp",
+            Error::RiskRejected { .. } => "risk_rejected",
+            Error::InvariantViolation { .. } => "invariant_violation",
+            Error::LedgerMismatch { .. } => "ledger_mismatch",
+            Error::InvalidConfig { .. } => "invalid_config",
+            Error::WriteOutput { .. } => "write_output",
+        }
+    }
 }
 
 pub fn build_csv_reader<R: Read>(stream_reader: R) -> Reader<R> {
@@ -48,11 +283,23 @@ pub fn build_csv_reader<R: Read>(stream_reader: R) -> Reader<R> {
         .from_reader(stream_reader)
 }
 
+/// a deposit or withdrawal kept for a possible dispute
+/// withdrawal is recorded as negative amount
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionRecord {
+    // the ClientID here is used to prevent the dispute with wrong clientID
+    pub client: ClientID,
+    pub amount: Amount,
+    // the part that is neither held by a dispute nor charged back, with the sign of `amount`
+    pub outstanding: Amount,
+    // when the transaction happened, to check the dispute window
+    pub timestamp: Option<Timestamp>,
+}
+
 // the structure that holds the record of transactions
-// it keeps the pair of <TransactionID, (ClientID, Amount)>
-// the ClientID here is not necessary since it is used to prevent the dispute with wrong clientID(which may not happen :)
+// it keeps the pair of <TransactionID, TransactionRecord>
 pub struct TransactionHashmapDB {
-    transactions: HashMap<TransactionID, (ClientID, Amount)>,
+    transactions: HashMap<TransactionID, TransactionRecord>,
 }
 impl TransactionHashmapDB {
     pub fn new() -> Self {
@@ -63,41 +310,42 @@ impl TransactionHashmapDB {
 
     // add a transaction into the record
     pub fn add_transaction(&mut self, transaction: &Transaction) {
-        match transaction.t_type() {
-            TransactionType::Deposit => {
-                if let Some(amount) = transaction.amount() {
-                    self.transactions
-                        .insert(transaction.id(), (transaction.client_id(), amount));
-                }
-            }
-            TransactionType::Withdrawal => {
-                if let Some(amount) = transaction.amount() {
-                    self.transactions
-                        .insert(transaction.id(), (transaction.client_id(), -amount));
-                    // withdrawal is recorded as negative amount
-                }
-            }
-            _ => {}
-        }
+        let amount = match (transaction.t_type(), transaction.amount()) {
+            (TransactionType::Deposit, Some(amount)) => amount,
+            // withdrawal is recorded as negative amount
+            (TransactionType::Withdrawal, Some(amount)) => -amount,
+            _ => return,
+        };
+        self.transactions.insert(
+            transaction.id(),
+            TransactionRecord {
+                client: transaction.client_id(),
+                amount,
+                outstanding: amount,
+                timestamp: transaction.timestamp(),
+            },
+        );
     }
 
-    // pop a transaction from the record
-    pub fn pop_transaction(
+    // look up a transaction
+    pub fn get_transaction(&self, transaction_id: &TransactionID) -> Option<TransactionRecord> {
+        self.transactions.get(transaction_id).copied()
+    }
+
+    // update the outstanding amount of a transaction
+    pub fn get_transaction_mut(
         &mut self,
         transaction_id: &TransactionID,
-    ) -> Option<(TransactionID, (ClientID, Amount))> {
-        self.transactions.remove_entry(transaction_id)
+    ) -> Option<&mut TransactionRecord> {
+        self.transactions.get_mut(transaction_id)
     }
 
-    // recover a disputed transaction
-    pub fn recover_transaction(
+    // drop a transaction that is fully charged back, it can't be disputed anymore
+    pub fn remove_transaction(
         &mut self,
-        transaction_id: TransactionID,
-        client_id: ClientID,
-        amount: Amount,
-    ) {
-        self.transactions
-            .insert(transaction_id, (client_id, amount));
+        transaction_id: &TransactionID,
+    ) -> Option<TransactionRecord> {
+        self.transactions.remove(transaction_id)
     }
 }
 
@@ -1,64 +1,650 @@
-use csv_parser::{build_csv_reader, ClientAccount, ClientID, Transaction, TransactionHashmapDB};
-use std::{cell::RefCell, collections::HashMap, io::Read, path::Path, rc::Rc};
-
-/// process the csv that could be loaded into memory through reader
-/// reader could point to a file stream or tcp stream
-fn process_csv_from_reader<R: Read>(
-    stream_reader: R,
-    db: &mut ClientDatabase,
-    transaction_db: Rc<RefCell<TransactionHashmapDB>>,
-) {
-    let mut reader = build_csv_reader(stream_reader);
-    let mut raw_record = csv::ByteRecord::new();
-    let headers = reader
-        .byte_headers()
-        .expect("the csv should have a header")
-        .clone();
-    while reader
-        .read_byte_record(&mut raw_record)
-        .expect("can't read from the csv")
-    {
-        let transaction: Transaction = raw_record
-            .deserialize(Some(&headers))
-            .expect("failed to serialize the record to Transaction");
-        if let Some(client_account) = db.get_mut(&transaction.client_id()) {
-            // ignore the error, could add error handling here when we need to process error case
-            let _ = client_account.process_transaction(&transaction);
+use clap::{Args, Parser, Subcommand};
+use csv_parser::{
+    diff_accounts, format_amount, open_input, process_inputs, read_balances, serve_metrics, Amount,
+    ClientAccountInfo, ComplianceMonitor, DiffFormat, DiffKind, Engine, EngineConfig, Error,
+    InputFormat, InputOptions, InputSource, OutputFormat, OutputOrder, ProcessingStats, Rejection,
+    Transaction,
+};
+use serde::Serialize;
+use std::{
+    fs::File,
+    io::{IsTerminal, Write},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+use tracing::{error, warn};
+use tracing_subscriber::EnvFilter;
+
+// the input or data is fine
+const EXIT_OK: u8 = 0;
+// the data has problems: invalid records for `validate`, differences for `diff`
+const EXIT_FAILURE: u8 = 1;
+// the command couldn't run, e.g. missing input file or unwritable output
+// clap exits with the same code on a usage error
+const EXIT_ERROR: u8 = 2;
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  the data has problems(invalid records for validate, differences for diff,
+     a broken invariant for process --audit)
+  2  the command couldn't run(bad usage, missing input, unwritable output)";
+
+/// a toy payments engine that processes transactions and reports the client balances
+#[derive(Parser)]
+#[command(name = "csv_parser", version, about, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    /// the toml file with the policies of the engine
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// the level of the logs on stderr: off, error, warn, info, debug or trace,
+    /// or directives like `csv_parser::engine=debug`
+    #[arg(long, global = true, default_value = "warn")]
+    log_level: String,
+    /// the format of the logs: text or json
+    #[arg(long, global = true, default_value = "text")]
+    log_format: LogFormat,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::UnknownFormat {
+                format: s.to_string(),
+            }),
+        }
+    }
+}
+
+// the logs go to stderr, stdout only has the balances
+fn init_logging(level: &str, format: LogFormat) -> Result<(), String> {
+    let filter =
+        EnvFilter::try_new(level).map_err(|e| format!("invalid log level {}: {}", level, e))?;
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr);
+    match format {
+        LogFormat::Text => logs.try_init(),
+        LogFormat::Json => logs.json().try_init(),
+    }
+    .map_err(|e| e.to_string())?;
+    JSON_LOGS.store(matches!(format, LogFormat::Json), Ordering::Relaxed);
+    Ok(())
+}
+
+// with json logs, stderr only has json lines: the reports go through the logs too
+static JSON_LOGS: AtomicBool = AtomicBool::new(false);
+
+fn json_logs() -> bool {
+    JSON_LOGS.load(Ordering::Relaxed)
+}
+
+fn report_rejection(rejection: &Rejection) {
+    if json_logs() {
+        warn!(
+            source = %rejection.source,
+            client = rejection.client,
+            tx = rejection.tx,
+            t_type = ?rejection.t_type,
+            reason_code = rejection.reason_code.as_deref(),
+            error = %rejection.error,
+            kind = rejection.error.kind(),
+            "rejected"
+        );
+    } else {
+        eprintln!("{}", rejection);
+    }
+}
+
+fn report_error(e: &dyn std::fmt::Display) {
+    if json_logs() {
+        error!(error = %e);
+    } else {
+        eprintln!("{}", e);
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// process the transactions and write the final balances
+    Process(ProcessArgs),
+    /// parse and check the transactions without writing any balance
+    Validate(InputArgs),
+    /// print the summary counts of the transactions
+    Stats(InputArgs),
+    /// compare two balance files in the csv output format, e.g. to reconcile with another system
+    #[command(alias = "reconcile")]
+    Diff(DiffArgs),
+    /// rebuild the balances from a snapshot and/or a journal of transactions
+    Replay(ReplayArgs),
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// the transaction files, `-` reads from stdin
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// the input format(csv or jsonl), guessed from the file extension by default
+    #[arg(long)]
+    format: Option<InputFormat>,
+    /// interleave the inputs by their timestamp column
+    #[arg(long)]
+    merge: bool,
+}
+
+impl InputArgs {
+    fn sources(&self) -> Vec<InputSource> {
+        self.inputs
+            .iter()
+            .map(|input| InputSource::from_arg(input))
+            .collect()
+    }
+
+    fn options(&self) -> InputOptions {
+        InputOptions {
+            format: self.format,
+            merge: self.merge,
+        }
+    }
+}
+
+#[derive(Args)]
+struct OutputArgs {
+    /// write the balances into the file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// the format of the balances: csv, json or jsonl
+    #[arg(long, default_value = "csv")]
+    output_format: OutputFormat,
+    /// the order of the balances: client, total-desc or insertion
+    #[arg(long, default_value = "client")]
+    order: OutputOrder,
+    /// add the debt column to the balances
+    #[arg(long)]
+    with_debt: bool,
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    output: OutputArgs,
+    /// write every change of the account status into the csv file
+    #[arg(long)]
+    audit_trail: Option<PathBuf>,
+    /// write the statement of the disputes still open into the csv file
+    #[arg(long)]
+    disputes: Option<PathBuf>,
+    /// write the chargeback losses per reason code into the csv file
+    #[arg(long)]
+    chargeback_report: Option<PathBuf>,
+    /// write the clients in debt into the csv file
+    #[arg(long)]
+    debt_report: Option<PathBuf>,
+    /// write every decision of the risk rules into the csv file
+    #[arg(long)]
+    risk_decisions: Option<PathBuf>,
+    /// write the large transactions and the structuring patterns into the csv file
+    #[arg(long)]
+    compliance_report: Option<PathBuf>,
+    /// print the summary of the run on stderr
+    #[arg(long)]
+    summary: bool,
+    /// write the summary of the run into the json file
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+    /// serve the metrics in the prometheus text format on http://ADDR/metrics during the run
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+    /// check the invariants of the accounts after every transaction
+    /// and stop at the first transaction that breaks one
+    #[arg(long)]
+    audit: bool,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    left: PathBuf,
+    right: PathBuf,
+    /// how far the amounts could be apart, they are compared as they are printed by default
+    #[arg(long, value_parser = parse_tolerance)]
+    tolerance: Option<Amount>,
+    /// how the differences are printed: text, or csv and jsonl with a row per mismatched field
+    #[arg(long, default_value = "text")]
+    format: DiffFormat,
+}
+
+// a tolerance is a finite amount, 0 compares the amounts exactly
+fn parse_tolerance(s: &str) -> Result<Amount, String> {
+    match s.parse::<Amount>() {
+        Ok(tolerance) if tolerance.is_finite() && tolerance >= 0f32 => Ok(tolerance),
+        Ok(_) => Err(String::from(
+            "the tolerance must be a finite amount of at least 0",
+        )),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// the balances to start from, in the csv output format
+    #[arg(long, required_unless_present = "journal")]
+    snapshot: Option<PathBuf>,
+    /// the transactions applied on top of the snapshot, `-` reads from stdin
+    journal: Vec<String>,
+    /// the input format of the journal, guessed from the file extension by default
+    #[arg(long)]
+    format: Option<InputFormat>,
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+/// run the inputs through the engine, every rejection is reported on stderr
+/// every outcome but an input that can't be opened goes to `on_outcome`
+/// returns the number of rejections and whether every input could be opened, or the first
+/// broken invariant of the audit, the outcomes after it are dropped
+fn run_engine(
+    engine: &mut Engine,
+    inputs: &[InputSource],
+    options: &InputOptions,
+    mut on_outcome: impl FnMut(Result<&Transaction, &Rejection>),
+) -> Result<(usize, bool), Error> {
+    let mut rejected = 0;
+    let mut all_opened = true;
+    let mut violation = None;
+    process_inputs(engine, inputs, options, |outcome| {
+        // the engine is broken, nothing after it could be trusted
+        if violation.is_some() {
+            return;
+        }
+        match outcome {
+            Ok(transaction) => on_outcome(Ok(transaction)),
+            Err(rejection) => match rejection.error {
+                Error::InvariantViolation { .. } => violation = Some(rejection.error),
+                _ => {
+                    report_rejection(&rejection);
+                    rejected += 1;
+                    if let Error::OpenInput { .. } = rejection.error {
+                        all_opened = false;
+                    } else {
+                        on_outcome(Err(&rejection));
+                    }
+                }
+            },
+        }
+    });
+    match violation {
+        Some(violation) => Err(violation),
+        None => Ok((rejected, all_opened)),
+    }
+}
+
+fn write_balances(engine: &Engine, args: &OutputArgs) -> Result<(), Error> {
+    let stream_writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path).map_err(|err| Error::WriteOutput {
+            reason: format!("{}: {}", path.display(), err),
+        })?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut writer = args.output_format.writer_with_debt(
+        stream_writer,
+        engine.config().precision,
+        args.with_debt,
+    );
+    for record in engine.accounts(args.order) {
+        writer.write(record)?;
+    }
+    writer.finish()
+}
+
+// the accounts in debt with their debt column, with the precision of the balances
+fn write_debt_report(path: &Path, engine: &Engine) -> Result<(), Error> {
+    let file = File::create(path).map_err(|err| Error::WriteOutput {
+        reason: format!("{}: {}", path.display(), err),
+    })?;
+    let mut writer = OutputFormat::Csv.writer_with_debt(file, engine.config().precision, true);
+    engine
+        .accounts_in_debt()
+        .into_iter()
+        .try_for_each(|record| writer.write(record))?;
+    writer.finish()
+}
+
+// write the rows of a report into a csv file
+fn write_csv<T: Serialize>(path: &Path, rows: impl IntoIterator<Item = T>) -> Result<(), Error> {
+    let write_error = |err: csv::Error| Error::WriteOutput {
+        reason: format!("{}: {}", path.display(), err),
+    };
+    let mut writer = csv::Writer::from_path(path).map_err(write_error)?;
+    for row in rows {
+        writer.serialize(row).map_err(write_error)?;
+    }
+    writer.flush().map_err(|err| write_error(err.into()))
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let write_error = |reason: String| Error::WriteOutput {
+        reason: format!("{}: {}", path.display(), reason),
+    };
+    let file = File::create(path).map_err(|err| write_error(err.to_string()))?;
+    serde_json::to_writer_pretty(file, value).map_err(|err| write_error(err.to_string()))
+}
+
+// the negative balances go to stderr with the rejections, stdout only has the balances
+fn report_overdrawn(engine: &Engine) {
+    let precision = engine.config().precision;
+    for account in engine.overdrawn_accounts() {
+        let (available, total, limit) = (
+            format_amount(account.info.available, precision),
+            format_amount(account.info.total, precision),
+            format_amount(account.overdraft_limit(), precision),
+        );
+        if json_logs() {
+            warn!(
+                client = account.info.client,
+                available = %available,
+                total = %total,
+                overdraft_limit = %limit,
+                "overdrawn"
+            );
         } else {
-            let mut client =
-                ClientAccount::new_with_db(transaction.client_id(), transaction_db.clone());
-            // ignore the error, could add error handling here when we need to process error case
-            let _ = client.process_transaction(&transaction);
-            db.insert(transaction.client_id(), client);
+            eprintln!(
+                "client {} is overdrawn: available {}, total {}, overdraft limit {}",
+                account.info.client, available, total, limit
+            );
         }
     }
 }
 
-fn print_database(db: &mut ClientDatabase) {
-    let mut writer = csv::WriterBuilder::new()
-        .delimiter(b',')
-        .from_writer(std::io::stdout());
-    let records = db.iter().map(|(_, client_account)| &client_account.info);
-    for record in records {
-        let _ = writer.serialize(record);
+fn load_balances(path: &Path) -> Result<Vec<ClientAccountInfo>, Error> {
+    read_balances(open_input(path)?).map_err(|e| Error::OpenInput {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn process(args: &ProcessArgs, config: &EngineConfig) -> Result<u8, Error> {
+    let mut engine = Engine::with_config(config.clone());
+    if args.audit {
+        engine.enable_audit();
+    }
+    if let Some(addr) = args.metrics_addr {
+        match TcpListener::bind(addr) {
+            Ok(listener) => {
+                serve_metrics(listener, engine.metrics());
+            }
+            Err(e) => {
+                report_error(&format!("can't serve the metrics on {}: {}", addr, e));
+                return Ok(EXIT_ERROR);
+            }
+        }
+    }
+    // the monitor only watches, a flagged transaction is still applied
+    let mut compliance = ComplianceMonitor::new(config.compliance.clone());
+    let mut stats = ProcessingStats::new();
+    let started = Instant::now();
+    let (_, all_opened) = run_engine(
+        &mut engine,
+        &args.input.sources(),
+        &args.input.options(),
+        |outcome| {
+            if let Ok(transaction) = outcome {
+                compliance.observe(transaction);
+            }
+            stats.record(outcome);
+        },
+    )?;
+    // the whole run went through the audit, the accounts must hold what came in
+    // the ledger is only checked here, not after every transaction
+    engine.check_ledger()?;
+    stats.summarize(&engine, started.elapsed());
+    report_overdrawn(&engine);
+    if args.summary {
+        if json_logs() {
+            // a single json line like the logs
+            match serde_json::to_string(&stats) {
+                Ok(summary) => eprintln!("{}", summary),
+                Err(e) => report_error(&e),
+            }
+        } else {
+            eprint!("{}", stats);
+        }
+    }
+    if let Err(e) = write_balances(&engine, &args.output) {
+        report_error(&e);
+        return Ok(EXIT_ERROR);
+    }
+    let precision = engine.config().precision;
+    let reports = [
+        args.audit_trail
+            .as_ref()
+            .map(|path| write_csv(path, engine.audit_trail())),
+        args.disputes.as_ref().map(|path| {
+            let disputes = engine.open_disputes();
+            write_csv(
+                path,
+                disputes.iter().map(|dispute| dispute.record(precision)),
+            )
+        }),
+        args.chargeback_report.as_ref().map(|path| {
+            let losses = engine.chargeback_report().losses();
+            write_csv(path, losses.iter().map(|loss| loss.record(precision)))
+        }),
+        args.debt_report
+            .as_ref()
+            .map(|path| write_debt_report(path, &engine)),
+        args.risk_decisions
+            .as_ref()
+            .map(|path| write_csv(path, engine.risk_decisions())),
+        args.compliance_report.as_ref().map(|path| {
+            write_csv(
+                path,
+                compliance.flags().iter().map(|flag| flag.record(precision)),
+            )
+        }),
+        args.summary_json
+            .as_ref()
+            .map(|path| write_json(path, &stats)),
+    ];
+    for result in reports.into_iter().flatten() {
+        if let Err(e) = result {
+            report_error(&e);
+            return Ok(EXIT_ERROR);
+        }
+    }
+    if all_opened {
+        Ok(EXIT_OK)
+    } else {
+        Ok(EXIT_ERROR)
     }
-    writer.flush().expect("can't flush the buffer of writer");
 }
 
-type ClientDatabase = HashMap<ClientID, ClientAccount>;
+fn validate(args: &InputArgs, config: &EngineConfig) -> Result<u8, Error> {
+    let mut engine = Engine::with_config(config.clone());
+    // only a broken record fails the validation, the engine could refuse a sound one
+    let (mut invalid, mut refused) = (0, 0);
+    let (_, all_opened) =
+        run_engine(
+            &mut engine,
+            &args.sources(),
+            &args.options(),
+            |outcome| match outcome {
+                Err(rejection) if rejection.error.is_format_error() => invalid += 1,
+                Err(_) => refused += 1,
+                Ok(_) => {}
+            },
+        )?;
+    if refused > 0 {
+        report_error(&format!("{} transaction(s) refused by the engine", refused));
+    }
+    if !all_opened {
+        Ok(EXIT_ERROR)
+    } else if invalid > 0 {
+        report_error(&format!("{} invalid record(s)", invalid));
+        Ok(EXIT_FAILURE)
+    } else {
+        Ok(EXIT_OK)
+    }
+}
 
-fn main() {
-    // parse out the input file path
-    let args: Vec<String> = std::env::args().collect();
-    assert!(args.len() > 1);
-    let path = Path::new(&args[1]);
-    let f =
-        std::fs::File::open(path).unwrap_or_else(|_| panic!("can't find input file {:?}", path));
+fn stats(args: &InputArgs, config: &EngineConfig) -> Result<u8, Error> {
+    let mut engine = Engine::with_config(config.clone());
+    let mut stats = ProcessingStats::new();
+    let started = Instant::now();
+    // the same outcomes as `process --summary`
+    let (_, all_opened) = run_engine(&mut engine, &args.sources(), &args.options(), |outcome| {
+        stats.record(outcome)
+    })?;
+    stats.summarize(&engine, started.elapsed());
+    print!("{}", stats);
+    if all_opened {
+        Ok(EXIT_OK)
+    } else {
+        Ok(EXIT_ERROR)
+    }
+}
+
+fn diff(args: &DiffArgs, precision: usize) -> u8 {
+    let (left, right) = match (load_balances(&args.left), load_balances(&args.right)) {
+        (Ok(left), Ok(right)) => (left, right),
+        (Err(e), _) | (_, Err(e)) => {
+            report_error(&e);
+            return EXIT_ERROR;
+        }
+    };
+    let diffs = diff_accounts(&left, &right, args.tolerance, precision);
+    let mut records = diffs.iter().flat_map(|diff| diff.records(precision));
+    let printed = match args.format {
+        DiffFormat::Text => {
+            for record in records {
+                match (record.kind, record.field) {
+                    (DiffKind::MissingLeft, _) => {
+                        println!(
+                            "client {}: missing in {}",
+                            record.client,
+                            args.left.display()
+                        )
+                    }
+                    (DiffKind::MissingRight, _) => {
+                        println!(
+                            "client {}: missing in {}",
+                            record.client,
+                            args.right.display()
+                        )
+                    }
+                    (_, field) => println!(
+                        "client {}: {} {} != {}",
+                        record.client,
+                        field.map(|field| field.to_string()).unwrap_or_default(),
+                        record.left.unwrap_or_default(),
+                        record.right.unwrap_or_default()
+                    ),
+                }
+            }
+            Ok(())
+        }
+        DiffFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            records
+                .try_for_each(|record| writer.serialize(record))
+                .and_then(|_| writer.flush().map_err(csv::Error::from))
+                .map_err(|e| e.to_string())
+        }
+        DiffFormat::Jsonl => records
+            .try_for_each(|record| serde_json::to_string(&record).map(|line| println!("{}", line)))
+            .map_err(|e| e.to_string()),
+    };
+    if let Err(reason) = printed {
+        report_error(&Error::WriteOutput { reason });
+        return EXIT_ERROR;
+    }
+    if diffs.is_empty() {
+        EXIT_OK
+    } else {
+        EXIT_FAILURE
+    }
+}
 
-    // create transaction database and client database
-    let transaction_db = Rc::new(RefCell::new(TransactionHashmapDB::new()));
-    let mut db = ClientDatabase::new();
+fn replay(args: &ReplayArgs, config: &EngineConfig) -> Result<u8, Error> {
+    let mut engine = Engine::with_config(config.clone());
+    if let Some(snapshot) = &args.snapshot {
+        match load_balances(snapshot) {
+            Ok(balances) => balances
+                .into_iter()
+                .for_each(|info| engine.restore_account(info)),
+            Err(e) => {
+                report_error(&e);
+                return Ok(EXIT_ERROR);
+            }
+        }
+    }
+    let journal: Vec<InputSource> = args
+        .journal
+        .iter()
+        .map(|input| InputSource::from_arg(input))
+        .collect();
+    let options = InputOptions {
+        format: args.format,
+        merge: false,
+    };
+    let (_, all_opened) = run_engine(&mut engine, &journal, &options, |_| {})?;
+    if let Err(e) = write_balances(&engine, &args.output) {
+        report_error(&e);
+        return Ok(EXIT_ERROR);
+    }
+    if all_opened {
+        Ok(EXIT_OK)
+    } else {
+        Ok(EXIT_ERROR)
+    }
+}
 
-    process_csv_from_reader(f, &mut db, transaction_db);
-    print_database(&mut db);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if let Err(e) = init_logging(&cli.log_level, cli.log_format) {
+        report_error(&e);
+        return ExitCode::from(EXIT_ERROR);
+    }
+    let config = match &cli.config {
+        Some(path) => match EngineConfig::from_file(path) {
+            Ok(config) => config,
+            Err(e) => {
+                report_error(&e);
+                return ExitCode::from(EXIT_ERROR);
+            }
+        },
+        None => EngineConfig::default(),
+    };
+    let result = match &cli.command {
+        Command::Process(args) => process(args, &config),
+        Command::Validate(args) => validate(args, &config),
+        Command::Stats(args) => stats(args, &config),
+        Command::Diff(args) => Ok(diff(args, config.precision)),
+        Command::Replay(args) => replay(args, &config),
+    };
+    match result {
+        Ok(code) => ExitCode::from(code),
+        // a broken invariant or ledger of the audit, the outputs would be wrong
+        Err(e) => {
+            report_error(&e);
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
 }
@@ -0,0 +1,145 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use crate::{Error, Timestamp, Transaction, TransactionID, TransactionStream};
+
+/// merge several transaction streams into one chronological stream(k-way merge)
+/// every stream must be ordered by timestamp itself, ties are broken by the tx id
+/// the items carry the index of the stream they come from, so the errors can be
+/// attributed to their source. records out of order or without a timestamp are
+/// reported and skipped
+pub struct MergeReader<'a> {
+    streams: Vec<TransactionStream<'a>>,
+    // the next transaction of each stream, waiting to be merged
+    pending: Vec<Option<Transaction>>,
+    // the timestamp of the last transaction read from each stream
+    last_timestamps: Vec<Option<Timestamp>>,
+    heap: BinaryHeap<Reverse<(Timestamp, TransactionID, usize)>>,
+    errors: VecDeque<(usize, Error)>,
+    started: bool,
+}
+
+impl<'a> MergeReader<'a> {
+    pub fn new(streams: Vec<TransactionStream<'a>>) -> Self {
+        let count = streams.len();
+        Self {
+            streams,
+            pending: (0..count).map(|_| None).collect(),
+            last_timestamps: vec![None; count],
+            heap: BinaryHeap::with_capacity(count),
+            errors: VecDeque::new(),
+            started: false,
+        }
+    }
+
+    // read the next valid transaction of the stream into the heap
+    fn fill(&mut self, index: usize) {
+        for transaction in self.streams[index].by_ref() {
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(e) => {
+                    self.errors.push_back((index, e));
+                    continue;
+                }
+            };
+            let timestamp = match transaction.timestamp() {
+                Some(timestamp) => timestamp,
+                None => {
+                    self.errors.push_back((
+                        index,
+                        Error::MissingTimestamp {
+                            tx: transaction.id(),
+                        },
+                    ));
+                    continue;
+                }
+            };
+            if let Some(previous) = self.last_timestamps[index] {
+                if timestamp < previous {
+                    self.errors.push_back((
+                        index,
+                        Error::OutOfOrder {
+                            tx: transaction.id(),
+                            timestamp,
+                            previous,
+                        },
+                    ));
+                    continue;
+                }
+            }
+            self.last_timestamps[index] = Some(timestamp);
+            self.heap
+                .push(Reverse((timestamp, transaction.id(), index)));
+            self.pending[index] = Some(transaction);
+            return;
+        }
+    }
+}
+
+impl<'a> Iterator for MergeReader<'a> {
+    type Item = (usize, Result<Transaction, Error>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.started {
+            self.started = true;
+            for index in 0..self.streams.len() {
+                self.fill(index);
+            }
+        }
+        if let Some((index, e)) = self.errors.pop_front() {
+            return Some((index, Err(e)));
+        }
+        let Reverse((_, _, index)) = self.heap.pop()?;
+        let transaction = self.pending[index]
+            .take()
+            .expect("every entry in the heap has a pending transaction");
+        self.fill(index);
+        Some((index, Ok(transaction)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, InputFormat, MergeReader, TransactionStream};
+
+    fn stream(data: &'static str) -> TransactionStream<'static> {
+        InputFormat::Csv.reader(data.as_bytes())
+    }
+
+    #[test]
+    fn test_merge_by_timestamp_and_tx() {
+        let first =
+            stream("type,client,tx,amount,timestamp\ndeposit,1,1,1.0,10\ndeposit,1,4,1.0,30\n");
+        let second = stream("type,client,tx,amount,timestamp\ndeposit,2,3,1.0,5\ndeposit,2,2,1.0,10\ndeposit,2,5,1.0,40\n");
+        let merged: Vec<(usize, u32)> = MergeReader::new(vec![first, second])
+            .map(|(index, transaction)| (index, transaction.unwrap().id()))
+            .collect();
+        assert_eq!(merged, vec![(1, 3), (0, 1), (1, 2), (0, 4), (1, 5)]);
+    }
+
+    #[test]
+    fn test_out_of_order_and_missing_timestamp_are_reported() {
+        let first = stream("type,client,tx,amount,timestamp\ndeposit,1,1,1.0,10\ndeposit,1,2,1.0,5\ndeposit,1,3,1.0,\ndeposit,1,4,1.0,20\n");
+        let merged: Vec<_> = MergeReader::new(vec![first]).collect();
+        assert_eq!(merged.len(), 4);
+        assert!(matches!(merged[0], (0, Ok(_))));
+        assert!(matches!(
+            merged[1],
+            (
+                0,
+                Err(Error::OutOfOrder {
+                    tx: 2,
+                    timestamp: 5,
+                    previous: 10
+                })
+            )
+        ));
+        assert!(matches!(
+            merged[2],
+            (0, Err(Error::MissingTimestamp { tx: 3 }))
+        ));
+        assert!(matches!(&merged[3], (0, Ok(t)) if t.id() == 4));
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{Amount, Error, TransactionType};
+
+// how long a connection may stay silent or stop reading before it is dropped
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the upper bounds of the latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// the latency of the transactions of a type, with the buckets of `LATENCY_BUCKETS`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    // the observations of each bucket on its own, they are added up when rendered
+    pub buckets: [u64; LATENCY_BUCKETS.len()],
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// the values of the metrics at some point
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    // the transactions processed per type, accepted and rejected
+    pub accepted: BTreeMap<TransactionType, u64>,
+    pub rejected: BTreeMap<TransactionType, u64>,
+    // the errors per kind, see `Error::kind`
+    pub errors: BTreeMap<&'static str, u64>,
+    // the disputes the engine settled when they expired, per type of settlement
+    pub expired_disputes: BTreeMap<TransactionType, u64>,
+    pub open_disputes: i64,
+    pub held: f64,
+    pub latency: BTreeMap<TransactionType, Histogram>,
+}
+
+fn type_name(t_type: &TransactionType) -> String {
+    format!("{:?}", t_type).to_lowercase()
+}
+
+impl Metrics {
+    /// the metrics in the prometheus text format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        text.push_str(
+            "# HELP payments_transactions_total the transactions processed per type and outcome\n",
+        );
+        text.push_str("# TYPE payments_transactions_total counter\n");
+        for (outcome, counts) in [("accepted", &self.accepted), ("rejected", &self.rejected)] {
+            for (t_type, count) in counts {
+                let _ = writeln!(
+                    text,
+                    "payments_transactions_total{{type=\"{}\",outcome=\"{}\"}} {}",
+                    type_name(t_type),
+                    outcome,
+                    count
+                );
+            }
+        }
+        text.push_str("# HELP payments_errors_total the rejections per kind of error\n");
+        text.push_str("# TYPE payments_errors_total counter\n");
+        for (kind, count) in &self.errors {
+            let _ = writeln!(text, "payments_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+        text.push_str(
+            "# HELP payments_expired_disputes_total the disputes settled when they expired\n",
+        );
+        text.push_str("# TYPE payments_expired_disputes_total counter\n");
+        for (t_type, count) in &self.expired_disputes {
+            let _ = writeln!(
+                text,
+                "payments_expired_disputes_total{{settlement=\"{}\"}} {}",
+                type_name(t_type),
+                count
+            );
+        }
+        text.push_str("# HELP payments_open_disputes the disputes still open\n");
+        text.push_str("# TYPE payments_open_disputes gauge\n");
+        let _ = writeln!(text, "payments_open_disputes {}", self.open_disputes);
+        text.push_str("# HELP payments_held_funds the funds held in every account\n");
+        text.push_str("# TYPE payments_held_funds gauge\n");
+        let _ = writeln!(text, "payments_held_funds {}", self.held);
+        text.push_str(
+            "# HELP payments_transaction_duration_seconds the time the engine took per transaction\n",
+        );
+        text.push_str("# TYPE payments_transaction_duration_seconds histogram\n");
+        for (t_type, histogram) in &self.latency {
+            let name = type_name(t_type);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    text,
+                    "payments_transaction_duration_seconds_bucket{{type=\"{}\",le=\"{}\"}} {}",
+                    name, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                text,
+                "payments_transaction_duration_seconds_bucket{{type=\"{}\",le=\"+Inf\"}} {}",
+                name, histogram.count
+            );
+            let _ = writeln!(
+                text,
+                "payments_transaction_duration_seconds_sum{{type=\"{}\"}} {}",
+                name, histogram.sum
+            );
+            let _ = writeln!(
+                text,
+                "payments_transaction_duration_seconds_count{{type=\"{}\"}} {}",
+                name, histogram.count
+            );
+        }
+        text
+    }
+}
+
+/// the metrics of an engine, updated with every transaction
+/// it could be shared with another thread, e.g. the one serving them over http
+#[derive(Debug, Default)]
+pub struct MetricsRegistry {
+    metrics: Mutex<Metrics>,
+}
+
+impl MetricsRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// count a transaction that went through the engine, and how long it took
+    pub fn record(&self, t_type: TransactionType, result: &Result<(), Error>, elapsed: Duration) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        let counts = match result {
+            Ok(()) => &mut metrics.accepted,
+            Err(_) => &mut metrics.rejected,
+        };
+        *counts.entry(t_type).or_default() += 1;
+        if let Err(e) = result {
+            *metrics.errors.entry(e.kind()).or_default() += 1;
+        }
+        metrics
+            .latency
+            .entry(t_type)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// count an error that never reached the engine, e.g. a record that can't be parsed
+    pub fn record_error(&self, error: &Error) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        *metrics.errors.entry(error.kind()).or_default() += 1;
+    }
+
+    /// count a dispute the engine settled when it expired, with the type of its settlement
+    pub fn record_expiry(&self, settlement: TransactionType) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        *metrics.expired_disputes.entry(settlement).or_default() += 1;
+    }
+
+    /// follow the change of the open disputes and the held funds of an account
+    pub fn adjust(&self, open_disputes: i64, held: Amount) {
+        let mut metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        metrics.open_disputes += open_disputes;
+        metrics.held += f64::from(held);
+    }
+
+    pub fn snapshot(&self) -> Metrics {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+// answer a single http request, only `GET /metrics` is known
+fn respond(stream: TcpStream, registry: &MetricsRegistry) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // skip the headers
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", registry.snapshot().render()),
+        _ => ("404 Not Found", String::new()),
+    };
+    let mut stream = reader.into_inner();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// serve the metrics on `GET /metrics` in a background thread, each connection in a thread of its own
+/// so a slow client doesn't hold up the others, the listening thread lives as long as the process
+pub fn serve_metrics(listener: TcpListener, registry: Arc<MetricsRegistry>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let registry = Arc::clone(&registry);
+            // a broken or timed out connection only concerns its client
+            thread::spawn(move || respond(stream, &registry));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{serve_metrics, Error, MetricsRegistry, TransactionType};
+
+    #[test]
+    fn test_render_metrics() {
+        let registry = MetricsRegistry::new();
+        registry.record(TransactionType::Deposit, &Ok(()), Duration::from_micros(3));
+        registry.record(
+            TransactionType::Withdrawal,
+            &Err(Error::AccountLocked { client: 1 }),
+            Duration::from_millis(2),
+        );
+        registry.adjust(1, 2.5);
+        registry.record_expiry(TransactionType::Chargeback);
+        let metrics = registry.snapshot();
+        assert_eq!(metrics.accepted[&TransactionType::Deposit], 1);
+        assert_eq!(metrics.errors["account_locked"], 1);
+
+        let text = metrics.render();
+        for line in [
+            "payments_transactions_total{type=\"deposit\",outcome=\"accepted\"} 1",
+            "payments_transactions_total{type=\"withdrawal\",outcome=\"rejected\"} 1",
+            "payments_errors_total{kind=\"account_locked\"} 1",
+            "payments_expired_disputes_total{settlement=\"chargeback\"} 1",
+            "payments_open_disputes 1",
+            "payments_held_funds 2.5",
+            "payments_transaction_duration_seconds_bucket{type=\"deposit\",le=\"0.000001\"} 0",
+            "payments_transaction_duration_seconds_bucket{type=\"deposit\",le=\"0.000005\"} 1",
+            "payments_transaction_duration_seconds_bucket{type=\"withdrawal\",le=\"0.001\"} 0",
+            "payments_transaction_duration_seconds_bucket{type=\"withdrawal\",le=\"0.005\"} 1",
+            "payments_transaction_duration_seconds_count{type=\"withdrawal\"} 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "{} not in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn test_serve_metrics() {
+        let registry = Arc::new(MetricsRegistry::new());
+        registry.adjust(3, 0.0);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve_metrics(listener, registry);
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        // a client that never sends its request doesn't block the others
+        let _silent = TcpStream::connect(addr).unwrap();
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\npayments_open_disputes 3\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{AuditEntry, ClientAccount, ClientAccountInfo, Error, Transaction};
+
+/// what happened in the engine, handed to every observer
+#[derive(Debug)]
+pub enum Event<'a> {
+    // an accepted transaction, with the balances of the account before and after it
+    Applied {
+        transaction: &'a Transaction,
+        before: &'a ClientAccountInfo,
+        after: &'a ClientAccountInfo,
+    },
+    // a refused transaction, the account is left as it was
+    Rejected {
+        transaction: &'a Transaction,
+        account: &'a ClientAccountInfo,
+        error: &'a Error,
+    },
+    // a dispute of a transaction that had none open, right after its `Applied`
+    DisputeOpened {
+        transaction: &'a Transaction,
+        before: &'a ClientAccountInfo,
+        after: &'a ClientAccountInfo,
+    },
+    // the engine settled a dispute past `dispute_window.max_open`
+    DisputeExpired {
+        settlement: &'a Transaction,
+        before: &'a ClientAccountInfo,
+        after: &'a ClientAccountInfo,
+    },
+    // the account was frozen, locked, unlocked or closed, e.g. by a chargeback
+    StatusChanged(&'a AuditEntry),
+}
+
+/// get called with the events of the engine, e.g. to send a notification when an account gets locked
+/// the observers are called in the order they were added, after the engine is done with the transaction
+pub trait Observer {
+    fn on_event(&mut self, event: &Event);
+}
+
+/// the account as it was before a transaction, to tell what the transaction changed
+pub(crate) struct Observed {
+    pub(crate) info: ClientAccountInfo,
+    status_changes: usize,
+    open_disputes: usize,
+}
+
+impl Observed {
+    pub(crate) fn take(account: &ClientAccount) -> Self {
+        Self {
+            info: account.info.clone(),
+            status_changes: account.audit_trail().len(),
+            open_disputes: account.open_dispute_count(),
+        }
+    }
+
+    // the status changes made since the account was observed
+    // a new overdraft limit is in the audit trail too, but the status stays the same
+    pub(crate) fn status_changes<'a>(
+        &self,
+        account: &'a ClientAccount,
+    ) -> impl Iterator<Item = &'a AuditEntry> {
+        account.audit_trail()[self.status_changes..]
+            .iter()
+            .filter(|entry| entry.from != entry.to)
+    }
+
+    pub(crate) fn dispute_opened(&self, account: &ClientAccount) -> bool {
+        account.open_dispute_count() > self.open_disputes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{process_inputs, Engine, Event, InputOptions, InputSource, Observer};
+
+    // keep a line per event
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl Observer for Recorder {
+        fn on_event(&mut self, event: &Event) {
+            let line = match event {
+                Event::Applied {
+                    transaction,
+                    before,
+                    after,
+                } => format!(
+                    "{:?} {}: available {} -> {}, held {} -> {}",
+                    transaction.t_type(),
+                    transaction.id(),
+                    before.available,
+                    after.available,
+                    before.held,
+                    after.held
+                ),
+                Event::Rejected {
+                    transaction, error, ..
+                } => format!(
+                    "{:?} {} rejected: {}",
+                    transaction.t_type(),
+                    transaction.id(),
+                    error.kind()
+                ),
+                Event::DisputeOpened { transaction, .. } => {
+                    format!("dispute opened on {}", transaction.id())
+                }
+                Event::DisputeExpired { settlement, .. } => {
+                    format!("dispute of {} expired", settlement.id())
+                }
+                Event::StatusChanged(entry) => {
+                    format!("client {} {} -> {}", entry.client, entry.from, entry.to)
+                }
+            };
+            self.0.borrow_mut().push(line);
+        }
+    }
+
+    fn record(inputs: &[&str]) -> (Vec<String>, Vec<String>) {
+        let first = Rc::new(RefCell::new(vec![]));
+        let second = Rc::new(RefCell::new(vec![]));
+        let mut engine = Engine::new();
+        engine.add_observer(Box::new(Recorder(first.clone())));
+        engine.add_observer(Box::new(Recorder(second.clone())));
+        let inputs: Vec<InputSource> = inputs.iter().map(|i| InputSource::from_arg(i)).collect();
+        process_inputs(&mut engine, &inputs, &InputOptions::default(), |_| {});
+        let events = (first.borrow().clone(), second.borrow().clone());
+        events
+    }
+
+    #[test]
+    fn test_events_of_sample() {
+        let (events, again) = record(&[
+            "./sample_csv/all_transactions.csv",
+            "./sample_csv/cant_withdrawal.csv",
+        ]);
+        assert_eq!(
+            events,
+            vec![
+                "Deposit 1: available 0 -> 2, held 0 -> 0",
+                "Withdrawal 2: available 2 -> 0.5, held 0 -> 0",
+                "Dispute 1: available 0.5 -> -1.5, held 0 -> 2",
+                "dispute opened on 1",
+                "Resolve 1: available -1.5 -> 0.5, held 2 -> 0",
+                "Dispute 2: available 0.5 -> 2, held 0 -> -1.5",
+                "dispute opened on 2",
+                "Resolve 2: available 2 -> 0.5, held -1.5 -> 0",
+                "Deposit 3: available 0.5 -> 3.5, held 0 -> 0",
+                "Dispute 3: available 3.5 -> 0.5, held 0 -> 3",
+                "dispute opened on 3",
+                "Chargeback 3: available 0.5 -> 0.5, held 3 -> 0",
+                "client 1 active -> locked",
+                "Withdrawal 1 rejected: account_locked",
+            ]
+        );
+        // every observer sees the same events
+        assert_eq!(events, again);
+    }
+}
@@ -0,0 +1,326 @@
+use serde::Serialize;
+use std::{io::Write, str::FromStr};
+
+use crate::{AccountStatus, Amount, ClientAccountInfo, ClientID, Error};
+
+/// the number of decimal places of every amount in the output
+pub const DEFAULT_PRECISION: usize = 4;
+
+/// format the amount with exactly `precision` decimal places
+pub fn format_amount(amount: Amount, precision: usize) -> String {
+    let formatted = format!("{:.*}", precision, amount);
+    // a tiny negative rounding error shouldn't show up as -0.0000
+    match formatted.strip_prefix('-') {
+        Some(abs) if abs.chars().all(|c| c == '0' || c == '.') => abs.to_string(),
+        _ => formatted,
+    }
+}
+
+/// the supported formats of the account output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    // a pretty printed json array
+    Json,
+    // one json object per line
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// build an account writer of this format on top of the stream
+    pub fn writer<'a, W: Write + 'a>(
+        self,
+        stream_writer: W,
+        precision: usize,
+    ) -> Box<dyn AccountWriter + 'a> {
+        self.writer_with_debt(stream_writer, precision, false)
+    }
+
+    /// same as `writer`, with the `debt` column before the status if `debt` is set
+    pub fn writer_with_debt<'a, W: Write + 'a>(
+        self,
+        stream_writer: W,
+        precision: usize,
+        debt: bool,
+    ) -> Box<dyn AccountWriter + 'a> {
+        match self {
+            OutputFormat::Csv => {
+                Box::new(CsvAccountWriter::new(stream_writer, precision).with_debt(debt))
+            }
+            OutputFormat::Json => {
+                Box::new(JsonAccountWriter::new(stream_writer, precision).with_debt(debt))
+            }
+            OutputFormat::Jsonl => {
+                Box::new(JsonlAccountWriter::new(stream_writer, precision).with_debt(debt))
+            }
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::Jsonl),
+            _ => Err(Error::UnknownFormat {
+                format: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// the order of the accounts in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputOrder {
+    // the default, so that the same input always gives the same output
+    #[default]
+    ClientId,
+    // the largest total first
+    TotalDesc,
+    // the order the accounts showed up in the input
+    Insertion,
+}
+
+impl FromStr for OutputOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "client" | "client-id" => Ok(OutputOrder::ClientId),
+            "total-desc" => Ok(OutputOrder::TotalDesc),
+            "insertion" => Ok(OutputOrder::Insertion),
+            _ => Err(Error::UnknownOrder {
+                order: s.to_string(),
+            }),
+        }
+    }
+}
+
+/// the account as it shows up in the output, the amounts are already formatted
+#[derive(Serialize)]
+struct AccountRecord {
+    client: ClientID,
+    available: String,
+    held: String,
+    total: String,
+    // only with the debt column, it is the same for every record of an output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    debt: Option<String>,
+    status: AccountStatus,
+}
+
+impl AccountRecord {
+    fn new(info: &ClientAccountInfo, precision: usize, debt: bool) -> Self {
+        Self {
+            client: info.client,
+            available: format_amount(info.available, precision),
+            held: format_amount(info.held, precision),
+            total: format_amount(info.total, precision),
+            debt: debt.then(|| format_amount(info.debt, precision)),
+            status: info.status,
+        }
+    }
+}
+
+fn write_error<E: std::fmt::Display>(err: E) -> Error {
+    Error::WriteOutput {
+        reason: err.to_string(),
+    }
+}
+
+/// write the final account balances in some format
+pub trait AccountWriter {
+    fn write(&mut self, info: &ClientAccountInfo) -> Result<(), Error>;
+
+    /// flush everything that is buffered, must be called after the last record
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+pub struct CsvAccountWriter<W: Write> {
+    writer: csv::Writer<W>,
+    precision: usize,
+    debt: bool,
+}
+
+impl<W: Write> CsvAccountWriter<W> {
+    pub fn new(stream_writer: W, precision: usize) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .delimiter(b',')
+                .from_writer(stream_writer),
+            precision,
+            debt: false,
+        }
+    }
+
+    /// add the `debt` column
+    pub fn with_debt(mut self, debt: bool) -> Self {
+        self.debt = debt;
+        self
+    }
+}
+
+impl<W: Write> AccountWriter for CsvAccountWriter<W> {
+    fn write(&mut self, info: &ClientAccountInfo) -> Result<(), Error> {
+        self.writer
+            .serialize(AccountRecord::new(info, self.precision, self.debt))
+            .map_err(write_error)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+/// the json array is only written out in `finish`, since it has to be closed anyway
+pub struct JsonAccountWriter<W: Write> {
+    writer: W,
+    records: Vec<AccountRecord>,
+    precision: usize,
+    debt: bool,
+}
+
+impl<W: Write> JsonAccountWriter<W> {
+    pub fn new(stream_writer: W, precision: usize) -> Self {
+        Self {
+            writer: stream_writer,
+            records: Vec::new(),
+            precision,
+            debt: false,
+        }
+    }
+
+    /// add the `debt` column
+    pub fn with_debt(mut self, debt: bool) -> Self {
+        self.debt = debt;
+        self
+    }
+}
+
+impl<W: Write> AccountWriter for JsonAccountWriter<W> {
+    fn write(&mut self, info: &ClientAccountInfo) -> Result<(), Error> {
+        self.records
+            .push(AccountRecord::new(info, self.precision, self.debt));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        serde_json::to_writer_pretty(&mut self.writer, &self.records).map_err(write_error)?;
+        self.records.clear();
+        writeln!(self.writer).map_err(write_error)?;
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+pub struct JsonlAccountWriter<W: Write> {
+    writer: W,
+    precision: usize,
+    debt: bool,
+}
+
+impl<W: Write> JsonlAccountWriter<W> {
+    pub fn new(stream_writer: W, precision: usize) -> Self {
+        Self {
+            writer: stream_writer,
+            precision,
+            debt: false,
+        }
+    }
+
+    /// add the `debt` column
+    pub fn with_debt(mut self, debt: bool) -> Self {
+        self.debt = debt;
+        self
+    }
+}
+
+impl<W: Write> AccountWriter for JsonlAccountWriter<W> {
+    fn write(&mut self, info: &ClientAccountInfo) -> Result<(), Error> {
+        serde_json::to_writer(
+            &mut self.writer,
+            &AccountRecord::new(info, self.precision, self.debt),
+        )
+        .map_err(write_error)?;
+        writeln!(self.writer).map_err(write_error)
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format_amount, AccountStatus, ClientAccountInfo, OutputFormat};
+
+    fn write_all(format: OutputFormat, records: &[ClientAccountInfo]) -> String {
+        write_with_debt(format, records, false)
+    }
+
+    fn write_with_debt(format: OutputFormat, records: &[ClientAccountInfo], debt: bool) -> String {
+        let mut data = vec![];
+        {
+            let mut writer = format.writer_with_debt(&mut data, 4, debt);
+            for record in records {
+                writer.write(record).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn sample_accounts() -> Vec<ClientAccountInfo> {
+        let mut first = ClientAccountInfo::new(1);
+        first.available = 0.123;
+        first.held = 0.12345;
+        first.total = 0.2465;
+        let mut second = ClientAccountInfo::new(2);
+        second.status = AccountStatus::Frozen;
+        vec![first, second]
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(1.0, 4), "1.0000");
+        assert_eq!(format_amount(0.12345, 4), "0.1235");
+        assert_eq!(format_amount(-0.00001, 4), "0.0000");
+        assert_eq!(format_amount(-1.5, 4), "-1.5000");
+    }
+
+    #[test]
+    fn test_csv_output() {
+        assert_eq!(
+            write_all(OutputFormat::Csv, &sample_accounts()),
+            "client,available,held,total,status\n1,0.1230,0.1235,0.2465,active\n2,0.0000,0.0000,0.0000,frozen\n"
+        );
+        // the debt column is only there on demand
+        let mut accounts = sample_accounts();
+        accounts[1].debt = 2.5;
+        assert_eq!(
+            write_with_debt(OutputFormat::Csv, &accounts, true),
+            "client,available,held,total,debt,status\n1,0.1230,0.1235,0.2465,0.0000,active\n2,0.0000,0.0000,0.0000,2.5000,frozen\n"
+        );
+    }
+
+    #[test]
+    fn test_json_output() {
+        let data = write_all(OutputFormat::Json, &sample_accounts());
+        let value: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(value[0]["available"], "0.1230");
+        assert_eq!(value[1]["total"], "0.0000");
+        assert_eq!(value[1]["status"], "frozen");
+        assert_eq!(write_all(OutputFormat::Json, &[]), "[]\n");
+    }
+
+    #[test]
+    fn test_jsonl_output() {
+        assert_eq!(
+            write_all(OutputFormat::Jsonl, &sample_accounts()),
+            "{\"client\":1,\"available\":\"0.1230\",\"held\":\"0.1235\",\"total\":\"0.2465\",\"status\":\"active\"}\n\
+             {\"client\":2,\"available\":\"0.0000\",\"held\":\"0.0000\",\"total\":\"0.0000\",\"status\":\"frozen\"}\n"
+        );
+    }
+}
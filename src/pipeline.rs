@@ -0,0 +1,156 @@
+use tracing::{debug, info, info_span, warn};
+
+use crate::{Engine, InputFormat, InputSource, MergeReader, Rejection, Transaction};
+
+/// how the inputs of a run are read
+#[derive(Debug, Clone, Default)]
+pub struct InputOptions {
+    // overrides the format guessed from the file extension
+    pub format: Option<InputFormat>,
+    // interleave the inputs by timestamp instead of reading them one after another
+    pub merge: bool,
+}
+
+/// read every input and feed the transactions to the engine
+/// the handler gets each accepted transaction, or the rejection with its source
+/// inputs that can't be opened are handed to the handler as rejections too
+pub fn process_inputs<F>(
+    engine: &mut Engine,
+    inputs: &[InputSource],
+    options: &InputOptions,
+    mut handler: F,
+) where
+    F: FnMut(Result<&Transaction, Rejection>),
+{
+    let sources: Vec<String> = inputs.iter().map(|input| input.to_string()).collect();
+    let mut streams = Vec::with_capacity(inputs.len());
+    for (input, source) in inputs.iter().zip(&sources) {
+        match input.open() {
+            Ok(f) => {
+                let format = input.format(options.format);
+                debug!(source = %source, format = ?format, "opened the input");
+                streams.push(format.reader(f))
+            }
+            Err(e) => {
+                warn!(source = %source, error = %e, "can't open the input");
+                handler(Err(Rejection::new(source, None, e)));
+                // keep the stream indexes in line with the sources
+                streams.push(Box::new(std::iter::empty()));
+            }
+        }
+    }
+
+    let mut process = |index: usize, transaction| {
+        let source = &sources[index];
+        match transaction {
+            Ok(transaction) => match engine.process_transaction(&transaction) {
+                Ok(()) => handler(Ok(&transaction)),
+                Err(e) => handler(Err(Rejection::new(source, Some(&transaction), e))),
+            },
+            // skip the record we can't understand and keep going
+            Err(e) => {
+                warn!(source = %source, error = %e, "skipped an unreadable record");
+                engine.metrics().record_error(&e);
+                handler(Err(Rejection::new(source, None, e)))
+            }
+        }
+    };
+    if options.merge {
+        let _span = info_span!("inputs", count = sources.len()).entered();
+        info!("merging the inputs by timestamp");
+        for (index, transaction) in MergeReader::new(streams) {
+            process(index, transaction);
+        }
+    } else {
+        // the inputs are processed in order against the same engine, e.g. daily files in sequence
+        for (index, stream) in streams.into_iter().enumerate() {
+            let _span = info_span!("input", source = %sources[index]).entered();
+            info!("reading the input");
+            for transaction in stream {
+                process(index, transaction);
+            }
+            info!("finished the input");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{process_inputs, Engine, Error, InputOptions, InputSource};
+
+    #[test]
+    fn test_process_several_inputs_against_one_engine() {
+        let inputs = vec![
+            InputSource::from_arg("./sample_csv/locked_account.csv"),
+            InputSource::from_arg("./sample_csv/missing.csv"),
+            InputSource::from_arg("./sample_csv/wrong_dispute.csv"),
+        ];
+        let mut engine = Engine::new();
+        let mut accepted = 0;
+        let mut rejections = vec![];
+        process_inputs(
+            &mut engine,
+            &inputs,
+            &InputOptions::default(),
+            |outcome| match outcome {
+                Ok(_) => accepted += 1,
+                Err(rejection) => rejections.push(rejection),
+            },
+        );
+        assert_eq!(accepted, 4);
+        assert_eq!(rejections.len(), 4);
+        assert!(matches!(rejections[0].error, Error::OpenInput { .. }));
+        assert_eq!(rejections[0].source, "./sample_csv/missing.csv");
+        assert_eq!(rejections[1].source, "./sample_csv/locked_account.csv");
+        assert!(matches!(rejections[1].error, Error::AccountLocked { .. }));
+        // the deposit of the second file lands on the account locked by the first one
+        assert_eq!(rejections[2].source, "./sample_csv/wrong_dispute.csv");
+        assert!(matches!(rejections[2].error, Error::AccountLocked { .. }));
+        assert!(matches!(
+            rejections[3].error,
+            Error::NotFoundTransaction { .. }
+        ));
+    }
+
+    #[derive(Clone, Default)]
+    struct Logs(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Logs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_logs_have_the_input_and_transaction_spans() {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            process_inputs(
+                &mut Engine::new(),
+                &[InputSource::from_arg("./sample_csv/cant_withdrawal.csv")],
+                &InputOptions::default(),
+                |_| {},
+            )
+        });
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let rejected = logs.lines().find(|line| line.contains("rejected")).unwrap();
+        assert!(rejected.contains("input{source=./sample_csv/cant_withdrawal.csv}"));
+        assert!(rejected.contains("transaction{client=1 tx=1 t_type=Withdrawal}"));
+        assert!(rejected.contains("DEBUG"));
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use crate::{ClientID, Error, Transaction, TransactionID, TransactionType};
+
+/// a record or transaction that was refused, together with the input it came from
+#[derive(Debug)]
+pub struct Rejection {
+    pub source: String,
+    // unknown when the record couldn't even be parsed
+    pub client: Option<ClientID>,
+    pub tx: Option<TransactionID>,
+    pub t_type: Option<TransactionType>,
+    // the reason code of a rejected dispute row
+    pub reason_code: Option<String>,
+    pub error: Error,
+}
+
+impl Rejection {
+    pub fn new(source: &str, transaction: Option<&Transaction>, error: Error) -> Self {
+        Self {
+            source: source.to_string(),
+            client: transaction.map(|t| t.client_id()),
+            tx: transaction.map(|t| t.id()),
+            t_type: transaction.map(|t| t.t_type()),
+            reason_code: transaction
+                .and_then(|t| t.reason_code())
+                .map(|code| code.to_string()),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.client, self.tx, &self.reason_code) {
+            (Some(client), Some(tx), Some(reason_code)) => write!(
+                f,
+                "{}: client {} tx {}(reason code {}) rejected: {}",
+                self.source, client, tx, reason_code, self.error
+            ),
+            (Some(client), Some(tx), None) => write!(
+                f,
+                "{}: client {} tx {} rejected: {}",
+                self.source, client, tx, self.error
+            ),
+            _ => write!(f, "{}: {}", self.source, self.error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Rejection, Transaction, TransactionType};
+
+    #[test]
+    fn test_rejection_names_its_source() {
+        let transaction = Transaction::new(TransactionType::Dispute, 1, 7, None);
+        let rejection = Rejection::new(
+            "day1.csv",
+            Some(&transaction),
+            Error::NotFoundTransaction { client: 1, tx: 7 },
+        );
+        assert_eq!(
+            rejection.to_string(),
+            "day1.csv: client 1 tx 7 rejected: can't find transaction 7 in client 1 account"
+        );
+
+        let transaction = transaction.with_reason_code("fraud");
+        let rejection = Rejection::new(
+            "day1.csv",
+            Some(&transaction),
+            Error::NotFoundTransaction { client: 1, tx: 7 },
+        );
+        assert_eq!(
+            rejection.to_string(),
+            "day1.csv: client 1 tx 7(reason code fraud) rejected: can't find transaction 7 in client 1 account"
+        );
+
+        let rejection = Rejection::new(
+            "day2.csv",
+            None,
+            Error::ParseRecord {
+                line: 3,
+                reason: String::from("bad record"),
+            },
+        );
+        assert_eq!(
+            rejection.to_string(),
+            "day2.csv: can't parse the record at line 3: bad record"
+        );
+    }
+}
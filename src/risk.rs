@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    transaction::prune_window, Amount, ClientAccountInfo, ClientID, Error, Timestamp, Transaction,
+    TransactionID, TransactionType,
+};
+
+/// what a risk rule wants done with a transaction
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RiskAction {
+    // the transaction isn't applied
+    Reject,
+    // the transaction is applied, then the account is frozen
+    Freeze,
+}
+
+/// a decision of a risk rule on a transaction, only kept when the rule fires
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct RiskDecision {
+    pub rule: String,
+    pub client: ClientID,
+    pub tx: TransactionID,
+    pub action: RiskAction,
+    pub reason: String,
+}
+
+/// a check that runs before a transaction is applied
+/// the rules keep their own state, e.g. the recent withdrawals of each client
+pub trait RiskRule {
+    /// the id the decisions are recorded with
+    fn id(&self) -> &str;
+
+    /// look at the transaction before it is applied, with the account of its client as it is
+    /// the account of a new client is already there, empty and active
+    /// returns the action and why, or none if the rule doesn't fire
+    fn check(
+        &mut self,
+        transaction: &Transaction,
+        account: &ClientAccountInfo,
+    ) -> Option<(RiskAction, String)>;
+
+    /// the transaction was applied
+    fn observe(&mut self, _transaction: &Transaction) {}
+}
+
+/// the built-in rules, as they are written in the config
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RiskRuleConfig {
+    // reject a single withdrawal above the limit
+    MaxWithdrawal {
+        id: String,
+        limit: Amount,
+    },
+    // reject a withdrawal that takes the amount withdrawn within the last `transactions`
+    // transactions of the client and/or the last `window` above `max_total`
+    WithdrawalVelocity {
+        id: String,
+        max_total: Amount,
+        transactions: Option<usize>,
+        window: Option<Timestamp>,
+    },
+    // freeze the account when the disputes go above `max_ratio` of the deposits and withdrawals
+    // only once the client has `min_transactions` of them
+    DisputeRatio {
+        id: String,
+        max_ratio: f32,
+        #[serde(default)]
+        min_transactions: u32,
+    },
+}
+
+impl RiskRuleConfig {
+    pub fn id(&self) -> &str {
+        match self {
+            RiskRuleConfig::MaxWithdrawal { id, .. }
+            | RiskRuleConfig::WithdrawalVelocity { id, .. }
+            | RiskRuleConfig::DisputeRatio { id, .. } => id,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |reason: &str| {
+            Err(Error::InvalidConfig {
+                reason: format!("risk rule {}: {}", self.id(), reason),
+            })
+        };
+        match self {
+            RiskRuleConfig::MaxWithdrawal { limit, .. } if *limit < 0f32 || limit.is_nan() => {
+                invalid("the limit must be a positive amount")
+            }
+            RiskRuleConfig::WithdrawalVelocity { max_total, .. }
+                if *max_total < 0f32 || max_total.is_nan() =>
+            {
+                invalid("max_total must be a positive amount")
+            }
+            RiskRuleConfig::WithdrawalVelocity {
+                transactions: None,
+                window: None,
+                ..
+            } => invalid("transactions or window is needed"),
+            RiskRuleConfig::WithdrawalVelocity {
+                transactions: Some(0),
+                ..
+            } => invalid("transactions must be at least 1"),
+            RiskRuleConfig::DisputeRatio { max_ratio, .. }
+                if !(*max_ratio > 0f32 && *max_ratio <= 1f32) =>
+            {
+                invalid("max_ratio must be in (0, 1]")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn RiskRule> {
+        match self.clone() {
+            RiskRuleConfig::MaxWithdrawal { id, limit } => Box::new(MaxWithdrawal { id, limit }),
+            RiskRuleConfig::WithdrawalVelocity {
+                id,
+                max_total,
+                transactions,
+                window,
+            } => Box::new(WithdrawalVelocity {
+                id,
+                max_total,
+                transactions,
+                window,
+                recent: HashMap::new(),
+            }),
+            RiskRuleConfig::DisputeRatio {
+                id,
+                max_ratio,
+                min_transactions,
+            } => Box::new(DisputeRatio {
+                id,
+                max_ratio,
+                min_transactions,
+                counts: HashMap::new(),
+            }),
+        }
+    }
+}
+
+pub struct MaxWithdrawal {
+    id: String,
+    limit: Amount,
+}
+
+impl RiskRule for MaxWithdrawal {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn check(
+        &mut self,
+        transaction: &Transaction,
+        _account: &ClientAccountInfo,
+    ) -> Option<(RiskAction, String)> {
+        match (transaction.t_type(), transaction.amount()) {
+            (TransactionType::Withdrawal, Some(amount)) if amount > self.limit => Some((
+                RiskAction::Reject,
+                format!("withdrawal of {} above the limit {}", amount, self.limit),
+            )),
+            _ => None,
+        }
+    }
+}
+
+pub struct WithdrawalVelocity {
+    id: String,
+    max_total: Amount,
+    transactions: Option<usize>,
+    window: Option<Timestamp>,
+    // the recent transactions of each client: when, and how much was withdrawn
+    // without `transactions`, only the ones with a timestamp are kept
+    recent: HashMap<ClientID, VecDeque<(Option<Timestamp>, Amount)>>,
+}
+
+impl WithdrawalVelocity {
+    // drop what is out of the window at `now`, the transaction at `now` isn't in `recent` yet
+    fn prune(&mut self, client: ClientID, now: Option<Timestamp>) {
+        let recent = self.recent.entry(client).or_default();
+        if let Some(transactions) = self.transactions {
+            while recent.len() >= transactions {
+                recent.pop_front();
+            }
+        }
+        if let Some(window) = self.window {
+            // the transactions without timestamp only count toward `transactions`
+            prune_window(recent, window, now);
+        }
+    }
+}
+
+impl RiskRule for WithdrawalVelocity {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn check(
+        &mut self,
+        transaction: &Transaction,
+        _account: &ClientAccountInfo,
+    ) -> Option<(RiskAction, String)> {
+        let amount = match (transaction.t_type(), transaction.amount()) {
+            (TransactionType::Withdrawal, Some(amount)) => amount,
+            _ => return None,
+        };
+        self.prune(transaction.client_id(), transaction.timestamp());
+        let withdrawn: Amount = self.recent[&transaction.client_id()]
+            .iter()
+            .map(|(_, amount)| amount)
+            .sum();
+        (withdrawn + amount > self.max_total).then(|| {
+            (
+                RiskAction::Reject,
+                format!(
+                    "{} withdrawn recently, {} more is above the limit {}",
+                    withdrawn, amount, self.max_total
+                ),
+            )
+        })
+    }
+
+    fn observe(&mut self, transaction: &Transaction) {
+        let withdrawn = match (transaction.t_type(), transaction.amount()) {
+            (TransactionType::Withdrawal, Some(amount)) => amount,
+            (TransactionType::Deposit, _) => 0f32,
+            // only the deposits and withdrawals count as transactions here
+            _ => return,
+        };
+        self.prune(transaction.client_id(), transaction.timestamp());
+        // a transaction without timestamp is never in a time window
+        if self.transactions.is_none() && transaction.timestamp().is_none() {
+            return;
+        }
+        self.recent
+            .entry(transaction.client_id())
+            .or_default()
+            .push_back((transaction.timestamp(), withdrawn));
+    }
+}
+
+pub struct DisputeRatio {
+    id: String,
+    max_ratio: f32,
+    min_transactions: u32,
+    // the deposits and withdrawals, and the disputes of each client
+    counts: HashMap<ClientID, (u32, u32)>,
+}
+
+impl RiskRule for DisputeRatio {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn check(
+        &mut self,
+        transaction: &Transaction,
+        _account: &ClientAccountInfo,
+    ) -> Option<(RiskAction, String)> {
+        if transaction.t_type() != TransactionType::Dispute {
+            return None;
+        }
+        let (transactions, disputes) = self
+            .counts
+            .get(&transaction.client_id())
+            .copied()
+            .unwrap_or_default();
+        let ratio = (disputes + 1) as f32 / transactions.max(1) as f32;
+        (transactions >= self.min_transactions && ratio > self.max_ratio).then(|| {
+            (
+                RiskAction::Freeze,
+                format!(
+                    "{} disputes for {} transactions, above the ratio {}",
+                    disputes + 1,
+                    transactions,
+                    self.max_ratio
+                ),
+            )
+        })
+    }
+
+    fn observe(&mut self, transaction: &Transaction) {
+        let (transactions, disputes) = self.counts.entry(transaction.client_id()).or_default();
+        match transaction.t_type() {
+            TransactionType::Deposit | TransactionType::Withdrawal => *transactions += 1,
+            TransactionType::Dispute => *disputes += 1,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ClientAccountInfo, RiskAction, RiskRuleConfig, Transaction, TransactionType};
+
+    fn withdrawal(tx: u32, amount: f32, timestamp: u64) -> Transaction {
+        Transaction::new(TransactionType::Withdrawal, 1, tx, Some(amount)).with_timestamp(timestamp)
+    }
+
+    #[test]
+    fn test_max_withdrawal() {
+        let account = ClientAccountInfo::new(1);
+        let mut rule = RiskRuleConfig::MaxWithdrawal {
+            id: String::from("big"),
+            limit: 100.0,
+        }
+        .build();
+        assert_eq!(rule.check(&withdrawal(1, 100.0, 0), &account), None);
+        let (action, _) = rule.check(&withdrawal(2, 100.5, 0), &account).unwrap();
+        assert_eq!(action, RiskAction::Reject);
+    }
+
+    #[test]
+    fn test_withdrawal_velocity() {
+        let account = ClientAccountInfo::new(1);
+        let mut rule = RiskRuleConfig::WithdrawalVelocity {
+            id: String::from("velocity"),
+            max_total: 10.0,
+            transactions: Some(3),
+            window: Some(100),
+        }
+        .build();
+        for (tx, amount, timestamp) in [(1, 4.0, 0), (2, 4.0, 10)] {
+            assert_eq!(
+                rule.check(&withdrawal(tx, amount, timestamp), &account),
+                None
+            );
+            rule.observe(&withdrawal(tx, amount, timestamp));
+        }
+        // 4 + 4 + 3 within the last 3 transactions and 100
+        assert!(rule.check(&withdrawal(3, 3.0, 20), &account).is_some());
+        assert_eq!(rule.check(&withdrawal(3, 2.0, 20), &account), None);
+        // the first withdrawal is out of the window at 100
+        assert_eq!(rule.check(&withdrawal(3, 6.0, 100), &account), None);
+        // a deposit pushes the first withdrawal out of the last 3 transactions
+        let deposit =
+            Transaction::new(TransactionType::Deposit, 1, 4, Some(1.0)).with_timestamp(30);
+        rule.observe(&deposit);
+        assert_eq!(rule.check(&withdrawal(5, 6.0, 40), &account), None);
+        assert!(rule.check(&withdrawal(5, 6.5, 40), &account).is_some());
+    }
+
+    #[test]
+    fn test_withdrawal_velocity_without_timestamps() {
+        let account = ClientAccountInfo::new(1);
+        let withdrawal = |tx| Transaction::new(TransactionType::Withdrawal, 1, tx, Some(4.0));
+        // the window doesn't drop the transactions without timestamp, the count limits them
+        let mut rule = RiskRuleConfig::WithdrawalVelocity {
+            id: String::from("velocity"),
+            max_total: 10.0,
+            transactions: Some(3),
+            window: Some(100),
+        }
+        .build();
+        rule.observe(&withdrawal(1));
+        rule.observe(&withdrawal(2));
+        assert!(rule.check(&withdrawal(3), &account).is_some());
+        // 3 is out of the window at 1000, 1 out of the last 3 transactions
+        rule.observe(&withdrawal(3).with_timestamp(500));
+        assert_eq!(
+            rule.check(&withdrawal(4).with_timestamp(1000), &account),
+            None
+        );
+
+        // and a window alone never holds them
+        let mut rule = RiskRuleConfig::WithdrawalVelocity {
+            id: String::from("velocity"),
+            max_total: 10.0,
+            transactions: None,
+            window: Some(100),
+        }
+        .build();
+        for tx in 1..=3 {
+            rule.observe(&withdrawal(tx));
+        }
+        assert_eq!(rule.check(&withdrawal(4), &account), None);
+    }
+
+    #[test]
+    fn test_dispute_ratio() {
+        let account = ClientAccountInfo::new(1);
+        let mut rule = RiskRuleConfig::DisputeRatio {
+            id: String::from("disputes"),
+            max_ratio: 0.5,
+            min_transactions: 2,
+        }
+        .build();
+        let dispute = Transaction::new(TransactionType::Dispute, 1, 1, None);
+        // not enough transactions to tell yet
+        assert_eq!(rule.check(&dispute, &account), None);
+        for tx in 1..=4 {
+            rule.observe(&Transaction::new(
+                TransactionType::Deposit,
+                1,
+                tx,
+                Some(1.0),
+            ));
+        }
+        assert_eq!(rule.check(&dispute, &account), None);
+        rule.observe(&dispute);
+        rule.observe(&dispute);
+        let (action, _) = rule.check(&dispute, &account).unwrap();
+        assert_eq!(action, RiskAction::Freeze);
+    }
+}
@@ -0,0 +1,317 @@
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    time::Duration,
+};
+
+use crate::{
+    format_amount, AccountStatus, Amount, ClientID, Engine, Error, OutputOrder, Rejection,
+    Transaction, TransactionType, DEFAULT_PRECISION,
+};
+
+/// the counts of a run, fed with every outcome of `process_inputs`
+/// the balances and the throughput are filled in by `summarize` at the end of the run
+#[derive(Debug, Default, Clone)]
+pub struct ProcessingStats {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    // the disputes the engine settled when they expired, they aren't records of the input
+    pub expired_disputes: u64,
+    // freeze, lock, unlock, close and overdraft
+    pub admin: u64,
+    // transactions refused by the engine
+    pub rejected: u64,
+    // records that couldn't be read at all
+    pub unreadable: u64,
+    // the refused transactions of each type
+    pub rejected_by_type: BTreeMap<TransactionType, u64>,
+    // the rejections of each kind of error, see `Error::kind`
+    pub errors: BTreeMap<&'static str, u64>,
+    // the amounts of the accepted deposits and withdrawals
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    // from the final balances
+    pub held: Amount,
+    pub charged_back: Amount,
+    pub locked_accounts: u64,
+    pub elapsed_seconds: f64,
+    pub rows_per_second: f64,
+    clients: BTreeSet<ClientID>,
+    // the precision of the engine, set by `summarize`
+    precision: Option<usize>,
+}
+
+// the summary as it is serialized, the amounts formatted and only the number of clients
+#[derive(Serialize)]
+struct Summary<'a> {
+    deposits: u64,
+    withdrawals: u64,
+    disputes: u64,
+    resolves: u64,
+    chargebacks: u64,
+    expired_disputes: u64,
+    admin: u64,
+    rejected: u64,
+    unreadable: u64,
+    rejected_by_type: &'a BTreeMap<TransactionType, u64>,
+    errors: &'a BTreeMap<&'static str, u64>,
+    deposited: String,
+    withdrawn: String,
+    held: String,
+    charged_back: String,
+    locked_accounts: u64,
+    elapsed_seconds: f64,
+    rows_per_second: f64,
+    clients: usize,
+}
+
+impl ProcessingStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the decimal places of the amounts in the summary, the default until `summarize`
+    pub fn precision(&self) -> usize {
+        self.precision.unwrap_or(DEFAULT_PRECISION)
+    }
+
+    /// count an outcome, an input that couldn't be opened isn't a record and is left out
+    pub fn record(&mut self, outcome: Result<&Transaction, &Rejection>) {
+        match outcome {
+            Err(rejection) if matches!(rejection.error, Error::OpenInput { .. }) => {}
+            Ok(transaction) => {
+                self.clients.insert(transaction.client_id());
+                let counter = match transaction.t_type() {
+                    TransactionType::Deposit => &mut self.deposits,
+                    TransactionType::Withdrawal => &mut self.withdrawals,
+                    TransactionType::Dispute => &mut self.disputes,
+                    TransactionType::Resolve => &mut self.resolves,
+                    TransactionType::Chargeback => &mut self.chargebacks,
+                    TransactionType::Freeze
+                    | TransactionType::Lock
+                    | TransactionType::Unlock
+                    | TransactionType::Close
+                    | TransactionType::Overdraft => &mut self.admin,
+                };
+                *counter += 1;
+                match (transaction.t_type(), transaction.amount()) {
+                    (TransactionType::Deposit, Some(amount)) => self.deposited += amount,
+                    (TransactionType::Withdrawal, Some(amount)) => self.withdrawn += amount,
+                    _ => {}
+                }
+            }
+            Err(rejection) => {
+                *self.errors.entry(rejection.error.kind()).or_default() += 1;
+                match rejection.client {
+                    Some(client) => {
+                        self.clients.insert(client);
+                        self.rejected += 1;
+                    }
+                    None => self.unreadable += 1,
+                }
+                if let Some(t_type) = rejection.t_type {
+                    *self.rejected_by_type.entry(t_type).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    /// fill in the final balances of the engine, the disputes it settled and the throughput of the run
+    pub fn summarize(&mut self, engine: &Engine, elapsed: Duration) {
+        self.precision = Some(engine.config().precision);
+        self.expired_disputes = engine.metrics().snapshot().expired_disputes.values().sum();
+        let accounts = engine.accounts(OutputOrder::ClientId);
+        self.held = accounts.iter().map(|info| info.held).sum();
+        self.locked_accounts = accounts
+            .iter()
+            .filter(|info| info.status == AccountStatus::Locked)
+            .count() as u64;
+        self.charged_back = engine
+            .chargeback_report()
+            .losses()
+            .iter()
+            .map(|loss| loss.amount)
+            .sum();
+        self.elapsed_seconds = elapsed.as_secs_f64();
+        if self.elapsed_seconds > 0f64 {
+            self.rows_per_second = self.rows() as f64 / self.elapsed_seconds;
+        }
+    }
+
+    pub fn accepted(&self) -> u64 {
+        self.deposits
+            + self.withdrawals
+            + self.disputes
+            + self.resolves
+            + self.chargebacks
+            + self.admin
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.accepted() + self.rejected + self.unreadable
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+impl Serialize for ProcessingStats {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let amount = |amount: Amount| format_amount(amount, self.precision());
+        Summary {
+            deposits: self.deposits,
+            withdrawals: self.withdrawals,
+            disputes: self.disputes,
+            resolves: self.resolves,
+            chargebacks: self.chargebacks,
+            expired_disputes: self.expired_disputes,
+            admin: self.admin,
+            rejected: self.rejected,
+            unreadable: self.unreadable,
+            rejected_by_type: &self.rejected_by_type,
+            errors: &self.errors,
+            deposited: amount(self.deposited),
+            withdrawn: amount(self.withdrawn),
+            held: amount(self.held),
+            charged_back: amount(self.charged_back),
+            locked_accounts: self.locked_accounts,
+            elapsed_seconds: self.elapsed_seconds,
+            rows_per_second: self.rows_per_second,
+            clients: self.clients(),
+        }
+        .serialize(s)
+    }
+}
+
+impl fmt::Display for ProcessingStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let amount = |amount: Amount| format_amount(amount, self.precision());
+        writeln!(f, "records: {}", self.rows())?;
+        writeln!(f, "accepted: {}", self.accepted())?;
+        writeln!(f, "  deposit: {}", self.deposits)?;
+        writeln!(f, "  withdrawal: {}", self.withdrawals)?;
+        writeln!(f, "  dispute: {}", self.disputes)?;
+        writeln!(f, "  resolve: {}", self.resolves)?;
+        writeln!(f, "  chargeback: {}", self.chargebacks)?;
+        writeln!(f, "  admin: {}", self.admin)?;
+        writeln!(f, "expired disputes: {}", self.expired_disputes)?;
+        writeln!(f, "rejected: {}", self.rejected)?;
+        for (t_type, count) in &self.rejected_by_type {
+            writeln!(f, "  {}: {}", format!("{:?}", t_type).to_lowercase(), count)?;
+        }
+        writeln!(f, "unreadable: {}", self.unreadable)?;
+        writeln!(f, "errors:")?;
+        for (kind, count) in &self.errors {
+            writeln!(f, "  {}: {}", kind, count)?;
+        }
+        writeln!(f, "clients: {}", self.clients())?;
+        writeln!(f, "locked accounts: {}", self.locked_accounts)?;
+        writeln!(f, "deposited: {}", amount(self.deposited))?;
+        writeln!(f, "withdrawn: {}", amount(self.withdrawn))?;
+        writeln!(f, "held: {}", amount(self.held))?;
+        writeln!(f, "charged back: {}", amount(self.charged_back))?;
+        writeln!(f, "rows per second: {:.0}", self.rows_per_second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        process_inputs, Engine, EngineConfig, InputOptions, InputSource, ProcessingStats,
+        Transaction, TransactionType,
+    };
+
+    fn stats_of(sample: &str) -> ProcessingStats {
+        stats_with_config(sample, EngineConfig::default())
+    }
+
+    fn stats_with_config(sample: &str, config: EngineConfig) -> ProcessingStats {
+        let mut engine = Engine::with_config(config);
+        let mut stats = ProcessingStats::new();
+        process_inputs(
+            &mut engine,
+            &[InputSource::from_arg(sample)],
+            &InputOptions::default(),
+            |outcome| stats.record(outcome.as_ref().map(|t| *t)),
+        );
+        stats.summarize(&engine, Duration::from_secs(2));
+        stats
+    }
+
+    #[test]
+    fn test_stats_of_sample() {
+        let stats = stats_of("./sample_csv/all_transactions.csv");
+        assert_eq!(stats.accepted(), 9);
+        assert_eq!(stats.disputes, 3);
+        assert_eq!(stats.chargebacks, 1);
+        assert_eq!(stats.rejected, 0);
+        assert_eq!(stats.clients(), 1);
+        assert_eq!(stats.locked_accounts, 1);
+        assert_eq!(stats.rows_per_second, 4.5);
+    }
+
+    #[test]
+    fn test_rejections_per_type_and_error() {
+        let stats = stats_of("./sample_csv/cant_withdrawal.csv");
+        let summary: serde_json::Value = serde_json::to_value(&stats).unwrap();
+        assert_eq!(summary["rejected"], 1);
+        assert_eq!(summary["rejected_by_type"]["withdrawal"], 1);
+        assert_eq!(summary["errors"]["no_sufficient_funds"], 1);
+        assert_eq!(summary["clients"], 1);
+    }
+
+    #[test]
+    fn test_summary_with_the_precision_of_the_engine() {
+        let stats = stats_of("./sample_csv/all_transactions.csv");
+        let summary = serde_json::to_value(&stats).unwrap();
+        assert_eq!(summary["deposited"], "5.0000");
+        let config = EngineConfig {
+            precision: 2,
+            ..Default::default()
+        };
+        let stats = stats_with_config("./sample_csv/all_transactions.csv", config);
+        let summary = serde_json::to_value(&stats).unwrap();
+        assert_eq!(summary["deposited"], "5.00");
+        assert!(stats.to_string().contains("deposited: 5.00\n"));
+    }
+
+    #[test]
+    fn test_expired_disputes() {
+        let mut config = EngineConfig::default();
+        config.dispute_window.max_open = Some(10);
+        let mut engine = Engine::with_config(config);
+        let mut stats = ProcessingStats::new();
+        for (t_type, tx, amount, timestamp) in [
+            (TransactionType::Deposit, 1, Some(5.0), 0),
+            (TransactionType::Dispute, 1, None, 5),
+            (TransactionType::Deposit, 2, Some(1.0), 16),
+        ] {
+            let transaction = Transaction::new(t_type, 1, tx, amount).with_timestamp(timestamp);
+            engine.process_transaction(&transaction).unwrap();
+            stats.record(Ok(&transaction));
+        }
+        stats.summarize(&engine, Duration::from_secs(1));
+        assert_eq!(stats.expired_disputes, 1);
+        assert_eq!(stats.held, 0.0);
+        // the settlement isn't a record
+        assert_eq!(stats.rows(), 3);
+        assert_eq!(serde_json::to_value(&stats).unwrap()["expired_disputes"], 1);
+    }
+
+    #[test]
+    fn test_missing_input_isnt_a_record() {
+        let stats = stats_of("./sample_csv/missing.csv");
+        assert_eq!(stats.rows(), 0);
+        assert!(stats.errors.is_empty());
+    }
+}
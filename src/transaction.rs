@@ -1,14 +1,29 @@
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 
 pub type ClientID = u16;
 pub type TransactionID = u32;
 pub type Amount = f32;
+// the time of a transaction, e.g. seconds since the unix epoch
+pub type Timestamp = u64;
+
+// drop the entries that are out of `window` at `now`, they don't have to be in order
+// the window only applies to the entries with a timestamp, and nothing is dropped without `now`
+pub(crate) fn prune_window<T>(
+    recent: &mut VecDeque<(Option<Timestamp>, T)>,
+    window: Timestamp,
+    now: Option<Timestamp>,
+) {
+    if let Some(now) = now {
+        recent.retain(|(then, _)| then.is_none_or(|then| now.saturating_sub(then) < window));
+    }
+}
 
 //#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 //pub struct ClientID(pub u16);
 //impl fmt::Display for ClientID {
 //fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//// Use `self.number` to refer to each positional data point.
+// // Use `self.number` to refer to each positional data point.
 //write!(f, "{}", self.0)
 //}
 //}
@@ -17,7 +32,7 @@ pub type Amount = f32;
 //pub struct TransactionID(pub u32); // a unique id for transaction
 //impl fmt::Display for TransactionID {
 //fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//// Use `self.number` to refer to each positional data point.
+// // Use `self.number` to refer to each positional data point.
 //write!(f, "{}", self.0)
 //}
 //}
@@ -26,12 +41,12 @@ pub type Amount = f32;
 //pub struct Amount(pub f32);
 //impl fmt::Display for Amount {
 //fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-//// Use `self.number` to refer to each positional data point.
+// // Use `self.number` to refer to each positional data point.
 //write!(f, "{}", self.0)
 //}
 //}
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -39,6 +54,30 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    // the administrative operations on the account, they don't move any funds
+    // block withdrawals only
+    Freeze,
+    // block every transaction
+    Lock,
+    // back to active from frozen or locked, a reason is required
+    Unlock,
+    // close the account for good, the balance must be zero
+    Close,
+    // set the overdraft limit of the client to the amount
+    Overdraft,
+}
+
+impl TransactionType {
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Freeze
+                | TransactionType::Lock
+                | TransactionType::Unlock
+                | TransactionType::Close
+                | TransactionType::Overdraft
+        )
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -48,6 +87,16 @@ pub struct Transaction {
     client: ClientID,
     tx: TransactionID,
     amount: Option<Amount>,
+    // optional, only needed when several streams are merged chronologically
+    #[serde(default)]
+    timestamp: Option<Timestamp>,
+    // why an administrative operation is done, kept in the audit trail
+    // on a dispute row, the free-text note that goes with the reason code
+    #[serde(default)]
+    reason: Option<String>,
+    // the code of the reason of a dispute, e.g. the card scheme reason code
+    #[serde(default)]
+    reason_code: Option<String>,
 }
 
 impl Transaction {
@@ -62,9 +111,27 @@ impl Transaction {
             client,
             tx,
             amount,
+            timestamp: None,
+            reason: None,
+            reason_code: None,
         }
     }
 
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn with_reason_code(mut self, reason_code: &str) -> Self {
+        self.reason_code = Some(reason_code.to_string());
+        self
+    }
+
     pub fn t_type(&self) -> TransactionType {
         self.t_type
     }
@@ -80,6 +147,18 @@ impl Transaction {
     pub fn amount(&self) -> Option<Amount> {
         self.amount
     }
+
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        self.timestamp
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    pub fn reason_code(&self) -> Option<&str> {
+        self.reason_code.as_deref()
+    }
 }
 
 #[cfg(test)]
@@ -94,6 +173,40 @@ mod tests {
         let mut raw_record = csv::ByteRecord::new();
         let headers = reader.byte_headers().unwrap().clone();
         reader.read_byte_record(&mut raw_record).unwrap();
-        let _: Transaction = raw_record.deserialize(Some(&headers)).unwrap();
+        let transaction: Transaction = raw_record.deserialize(Some(&headers)).unwrap();
+        assert_eq!(transaction.timestamp(), None);
+    }
+
+    #[test]
+    fn deserialize_transaction_with_timestamp() {
+        let data = "type, client, tx, amount, timestamp\ndeposit, 1, 1, 2.0, 1656000000\ndispute, 1, 1,, 1656000060\n";
+        let mut reader = build_csv_reader(data.as_bytes());
+        let transactions: Vec<Transaction> =
+            reader.deserialize().map(|record| record.unwrap()).collect();
+        assert_eq!(transactions[0].timestamp(), Some(1656000000));
+        assert_eq!(transactions[1].amount(), None);
+        assert_eq!(transactions[1].timestamp(), Some(1656000060));
+    }
+
+    #[test]
+    fn deserialize_admin_transaction_with_reason() {
+        let data = "type, client, tx, amount, timestamp, reason\nunlock, 1, 9,,, cleared by support\nfreeze, 1, 10\n";
+        let mut reader = build_csv_reader(data.as_bytes());
+        let transactions: Vec<Transaction> =
+            reader.deserialize().map(|record| record.unwrap()).collect();
+        assert!(transactions[0].t_type().is_admin());
+        assert_eq!(transactions[0].reason(), Some("cleared by support"));
+        assert_eq!(transactions[1].reason(), None);
+    }
+
+    #[test]
+    fn deserialize_dispute_with_reason_code() {
+        let data = "type, client, tx, amount, timestamp, reason, reason_code\ndispute, 1, 1,,, item never arrived, 13.1\ndispute, 1, 2\n";
+        let mut reader = build_csv_reader(data.as_bytes());
+        let transactions: Vec<Transaction> =
+            reader.deserialize().map(|record| record.unwrap()).collect();
+        assert_eq!(transactions[0].reason_code(), Some("13.1"));
+        assert_eq!(transactions[0].reason(), Some("item never arrived"));
+        assert_eq!(transactions[1].reason_code(), None);
     }
 }